@@ -0,0 +1,41 @@
+use exif::{Exif, In, Tag, Value};
+
+// See: https://en.wikipedia.org/wiki/APEX_system
+
+// Tv = -log2(t), with t in seconds
+pub(in crate) fn time_value_to_seconds(time_value: f64) -> f64 {
+    2f64.powf(-time_value)
+}
+
+// Av = 2 * log2(N), with N the f-number
+pub(in crate) fn aperture_value_to_f_number(aperture_value: f64) -> f64 {
+    2f64.powf(aperture_value / 2.0)
+}
+
+// APEX tags are signed or unsigned rationals depending on the tag. A zero denominator is used by
+// some cameras to mean "unknown", so non-finite values are discarded
+fn get_apex_value(exif: &Exif, tag: Tag) -> Option<f64> {
+    let field = exif.get_field(tag, In::PRIMARY)?;
+    let value = match &field.value {
+        Value::SRational(data) => data.first()?.to_f64(),
+        Value::Rational(data) => data.first()?.to_f64(),
+        _ => return None,
+    };
+    Some(value).filter(|x| x.is_finite())
+}
+
+// Exposure time in seconds, from ShutterSpeedValue
+pub(in crate) fn get_shutter_speed(exif: &Exif) -> Option<f32> {
+    get_apex_value(exif, Tag::ShutterSpeedValue)
+        .map(time_value_to_seconds)
+        .filter(|x| x.is_finite())
+        .map(|x| x as f32)
+}
+
+// F-number, from ApertureValue
+pub(in crate) fn get_aperture(exif: &Exif) -> Option<f32> {
+    get_apex_value(exif, Tag::ApertureValue)
+        .map(aperture_value_to_f_number)
+        .filter(|x| x.is_finite())
+        .map(|x| x as f32)
+}
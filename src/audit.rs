@@ -0,0 +1,107 @@
+use crate::apex::{get_aperture, get_shutter_speed};
+use crate::error::Error;
+use crate::metadata::{get_canon_shotinfo, get_exposure_time};
+use exif::{Exif, In, Tag, Value};
+
+const SHOTINFO_EXPOSURE_TIME: usize = 22;
+const SHOTINFO_BULB_DURATION: usize = 24;
+
+// Maximum allowed difference, in stops. APEX values are commonly rounded to the nearest 1/3 stop
+const TOLERANCE_STOPS: f32 = 0.5;
+
+#[derive(Debug)]
+pub(in crate) struct Mismatch {
+    // Tag which disagrees with the reference tag
+    pub source: &'static str,
+    pub value: f32,
+    pub reference: &'static str,
+    pub reference_value: f32,
+    // Size of the disagreement, in stops
+    pub stops: f32,
+}
+
+// Canon encodes exposure values in 1/32 EV units, with 0x0c and 0x14 meaning 1/3 and 2/3 steps
+fn canon_ev(value: i16) -> f32 {
+    let magnitude = i32::from(value).abs();
+    let fraction = match magnitude & 0x1f {
+        0x0c => 32.0 / 3.0,
+        0x14 => 64.0 / 3.0,
+        x => x as f32,
+    };
+    let ev = ((magnitude & !0x1f) as f32 + fraction) / 32.0;
+    if value < 0 {
+        -ev
+    } else {
+        ev
+    }
+}
+
+fn get_canon_exposure_time(exif: &Exif) -> Option<(&'static str, f32)> {
+    let shotinfo = get_canon_shotinfo(exif).ok()?;
+    let bulb_duration = *shotinfo.get(SHOTINFO_BULB_DURATION)?;
+    if bulb_duration > 0 {
+        return Some(("Canon BulbDuration", f32::from(bulb_duration)));
+    }
+    // Zero means the value was not recorded
+    let encoded = *shotinfo.get(SHOTINFO_EXPOSURE_TIME)?;
+    if encoded == 0 {
+        return None;
+    }
+    Some(("Canon ExposureTime", 2f32.powf(-canon_ev(encoded as i16))))
+}
+
+fn get_f_number(exif: &Exif) -> Option<f32> {
+    let field = exif.get_field(Tag::FNumber, In::PRIMARY)?;
+    if let Value::Rational(data) = &field.value {
+        Some(data.first()?.to_f64() as f32).filter(|x| x.is_finite() && *x > 0.0)
+    } else {
+        None
+    }
+}
+
+fn audit_exposure_time(exif: &Exif) -> Result<Vec<Mismatch>, Error> {
+    let exposure_time = get_exposure_time(exif)?;
+
+    let mut candidates = vec![];
+    if let Some(x) = get_shutter_speed(exif) {
+        candidates.push(("ShutterSpeedValue", x));
+    }
+    if let Some(x) = get_canon_exposure_time(exif) {
+        candidates.push(x);
+    }
+
+    Ok(candidates
+        .into_iter()
+        .map(|(source, x)| Mismatch {
+            source,
+            value: x,
+            reference: "ExposureTime",
+            reference_value: exposure_time,
+            stops: (x / exposure_time).log2(),
+        })
+        .collect())
+}
+
+// Lenses without electronic contacts, or no lens at all as is common for darks, leave FNumber
+// unset, in which case there's nothing to check
+fn audit_aperture(exif: &Exif) -> Vec<Mismatch> {
+    match (get_f_number(exif), get_aperture(exif)) {
+        (Some(f_number), Some(aperture)) => vec![Mismatch {
+            source: "ApertureValue",
+            value: aperture,
+            reference: "FNumber",
+            reference_value: f_number,
+            // One stop is a factor of sqrt(2) in f-number
+            stops: 2.0 * (aperture / f_number).log2(),
+        }],
+        _ => vec![],
+    }
+}
+
+// Cross checks tags which record the same exposure parameters
+pub(in crate) fn audit_exposure(exif: &Exif) -> Result<Vec<Mismatch>, Error> {
+    let mut mismatches = audit_exposure_time(exif)?;
+    mismatches.extend(audit_aperture(exif));
+    mismatches.retain(|x| x.stops.is_nan() || x.stops.abs() > TOLERANCE_STOPS);
+    Ok(mismatches)
+}
@@ -0,0 +1,216 @@
+use crate::error::Error;
+use crate::metadata::ImageMetadata;
+use crate::tiff::ParseOptions;
+use crate::xmp::find_sidecar;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+// Entries are a few hundred bytes each, so this caps the cache at tens of megabytes
+const MAX_ENTRIES: usize = 100_000;
+
+// 64-bit FNV-1a. Entry names have to be the same across runs and Rust releases, which isn't
+// guaranteed for the standard library's hashers
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(FNV_OFFSET_BASIS, |hash, x| {
+        (hash ^ u64::from(*x)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+// Metadata of files which haven't changed since they were last read, stored as one JSON file per
+// image under the XDG cache directory. Once there are more than MAX_ENTRIES, the least recently
+// written are evicted
+pub(in crate) struct MetadataCache {
+    directory: PathBuf,
+    // Results depend on the darkmagic version and the parse options, so entries from a different
+    // configuration are ignored
+    parser: String,
+}
+
+// Size and modification time of a file. If either changes, the file is read again
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct Stamp {
+    size: u64,
+    modified_secs: u64,
+    modified_nanos: u32,
+}
+
+impl Stamp {
+    fn new(path: &Path) -> io::Result<Stamp> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Ok(Stamp {
+            size: metadata.len(),
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry<M> {
+    path: PathBuf,
+    parser: String,
+    image: Stamp,
+    // The sidecar overrides the image's metadata, so it's part of the key too
+    sidecar: Option<Stamp>,
+    metadata: M,
+}
+
+// Location in the cache of one image. The stamps are taken before the image is read, so that a
+// file which changes while it's being read is read again next time
+pub(in crate) struct CacheSlot<'a> {
+    cache: &'a MetadataCache,
+    file: PathBuf,
+    path: PathBuf,
+    image: Stamp,
+    sidecar: Option<Stamp>,
+}
+
+fn cache_home() -> Option<PathBuf> {
+    // Relative paths are invalid, per the XDG base directory specification
+    match env::var_os("XDG_CACHE_HOME").map(PathBuf::from) {
+        Some(x) if x.is_absolute() => Some(x),
+        _ => env::var_os("HOME").map(|x| PathBuf::from(x).join(".cache")),
+    }
+}
+
+impl MetadataCache {
+    pub fn new(options: ParseOptions) -> Result<MetadataCache, Error> {
+        let directory = cache_home()
+            .ok_or_else(|| {
+                Error::Unsupported("No cache directory, since HOME is not set".to_string())
+            })?
+            .join("darkmagic");
+        fs::create_dir_all(&directory)?;
+        if let Err(err) = evict(&directory) {
+            warn!("Failed to evict cache entries: {}", err);
+        }
+        Ok(MetadataCache {
+            directory,
+            parser: format!(
+                "{} {:?} {}",
+                env!("CARGO_PKG_VERSION"),
+                options.mode,
+                options.max_value_bytes
+            ),
+        })
+    }
+
+    pub fn slot(&self, path: &Path) -> Result<CacheSlot<'_>, Error> {
+        let path = fs::canonicalize(path)?;
+        // Lossy, but paths which collide are told apart by the path stored in the entry
+        let hash = fnv1a(path.to_string_lossy().as_bytes());
+        Ok(CacheSlot {
+            cache: self,
+            file: self.directory.join(format!("{:016x}.json", hash)),
+            image: Stamp::new(&path)?,
+            sidecar: find_sidecar(&path).map(|x| Stamp::new(&x)).transpose()?,
+            path,
+        })
+    }
+}
+
+// Removes the least recently written entries, so that at most MAX_ENTRIES remain. Run once per
+// process, rather than on every write, since it lists the whole directory
+fn evict(directory: &Path) -> io::Result<()> {
+    let mut entries = vec![];
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        if entry.path().extension() == Some(OsStr::new("json")) {
+            entries.push((entry.metadata()?.modified()?, entry.path()));
+        }
+    }
+    if entries.len() <= MAX_ENTRIES {
+        return Ok(());
+    }
+    entries.sort();
+    let excess = entries.len() - MAX_ENTRIES;
+    debug!("Evicting {} cache entries", excess);
+    for (_, path) in entries.into_iter().take(excess) {
+        // Another process may have evicted or replaced it already
+        if let Err(err) = fs::remove_file(&path) {
+            debug!("Failed to remove cache entry {}: {}", path.display(), err);
+        }
+    }
+    Ok(())
+}
+
+impl<'a> CacheSlot<'a> {
+    // Returns the cached metadata, if the image and its sidecar are unchanged
+    pub fn get(&self) -> Option<ImageMetadata> {
+        let data = fs::read(&self.file).ok()?;
+        let entry: Entry<ImageMetadata> = match serde_json::from_slice(&data) {
+            Ok(entry) => entry,
+            Err(err) => {
+                debug!(
+                    "Ignoring invalid cache entry {}: {}",
+                    self.file.display(),
+                    err
+                );
+                return None;
+            }
+        };
+        // Different paths can hash to the same file
+        if entry.path == self.path
+            && entry.parser == self.cache.parser
+            && entry.image == self.image
+            && entry.sidecar == self.sidecar
+        {
+            Some(entry.metadata)
+        } else {
+            None
+        }
+    }
+
+    // Failing to write the cache doesn't fail the read, since the metadata is still valid
+    pub fn put(&self, metadata: &ImageMetadata) {
+        if let Err(err) = self.write(metadata) {
+            warn!(
+                "Failed to write cache entry {}: {}",
+                self.file.display(),
+                err
+            );
+        }
+    }
+
+    fn write(&self, metadata: &ImageMetadata) -> io::Result<()> {
+        let entry = Entry {
+            path: self.path.clone(),
+            parser: self.cache.parser.clone(),
+            image: self.image,
+            sidecar: self.sidecar,
+            metadata,
+        };
+        // Written to a temporary file first, so that concurrent runs never see a partial entry
+        let temporary = self
+            .file
+            .with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&temporary, serde_json::to_vec(&entry)?)?;
+        fs::rename(&temporary, &self.file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Entry names depend on these, so they must never change
+    #[test]
+    fn fnv1a_matches_reference() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::error::Error;
+use crate::heif::{child_boxes, invalid, read_body, read_box_header};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use exif::experimental::Writer;
+use exif::{Context, Exif, Field, In, Tag, Value};
+use std::convert::TryFrom;
+use std::io;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+// Canon's metadata box inside moov, used by EOS MP4 and CRM clips (and CR3 stills). It holds the
+// IFD0, EXIF, and maker note IFDs as separate TIFF files named CMT1, CMT2, and CMT3
+const CANON_UUID: [u8; 16] = [
+    0x85, 0xc0, 0xb6, 0x87, 0x82, 0x0f, 0x11, 0xe0, 0x81, 0x11, 0xf4, 0xce, 0x46, 0x2b, 0x6a, 0x48,
+];
+// The moov box of a long clip holds the sample tables, so can be fairly large
+const MAX_MOOV_BYTES: u64 = 64 * 1024 * 1024;
+
+// Checks for an ISO base media file, and rewinds the reader to the start of the file. HEIF images
+// must be checked for first, since they are also ISO base media files
+pub(in crate) fn is_video<R: Read + Seek>(reader: &mut R) -> io::Result<bool> {
+    let result = read_box_header(reader);
+    reader.seek(SeekFrom::Start(0))?;
+    match result {
+        Ok((box_type, _)) => Ok(&box_type == b"ftyp"),
+        Err(err)
+            if err.kind() == io::ErrorKind::UnexpectedEof
+                || err.kind() == io::ErrorKind::InvalidData =>
+        {
+            Ok(false)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+fn find_child<'a>(data: &'a [u8], box_type: &[u8; 4]) -> io::Result<Option<&'a [u8]>> {
+    Ok(child_boxes(data)?
+        .into_iter()
+        .find(|(x, _)| x == box_type)
+        .map(|(_, x)| x))
+}
+
+fn read_moov<R: Read + Seek>(reader: &mut R) -> io::Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(0))?;
+    loop {
+        let (box_type, size) = read_box_header(reader)?;
+        if &box_type == b"moov" {
+            return read_body(reader, size, MAX_MOOV_BYTES);
+        }
+        match size {
+            Some(x) => {
+                let skip = i64::try_from(x).map_err(|_| invalid("Invalid box size"))?;
+                reader.seek(SeekFrom::Current(skip))?;
+            }
+            None => return Err(invalid("No moov box in video file")),
+        }
+    }
+}
+
+fn tiff_byte_order(tiff: &[u8]) -> io::Result<bool> {
+    match tiff.get(..4) {
+        Some(b"II*\0") => Ok(true),
+        Some(b"MM\0*") => Ok(false),
+        _ => Err(invalid("Invalid TIFF header in Canon metadata box")),
+    }
+}
+
+// Reads the fields of the first IFD of a CMT box, assigning them to the given context
+fn read_cmt_fields(cmt: &[u8], context: Context) -> Result<Vec<Field>, Error> {
+    let exif = exif::Reader::new().read_raw(cmt.to_vec())?;
+    Ok(exif
+        .fields()
+        .filter(|x| x.ifd_num == In::PRIMARY && x.tag.0 == Context::Tiff)
+        .map(|x| Field {
+            tag: Tag(context, x.tag.1),
+            ifd_num: In::PRIMARY,
+            value: x.value.clone(),
+        })
+        .collect())
+}
+
+// CMT3 is a TIFF file whose pointers are relative to its own header. The Canon maker note parser
+// expects the IFD at the start, followed by a footer with the offset pointers are relative to, so
+// one is added
+fn cmt3_to_makernote(cmt3: &[u8]) -> io::Result<Vec<u8>> {
+    let little_endian = tiff_byte_order(cmt3)?;
+    let header = cmt3
+        .get(4..8)
+        .ok_or_else(|| invalid("Truncated TIFF header in CMT3"))?;
+    let ifd_offset = if little_endian {
+        LittleEndian::read_u32(header)
+    } else {
+        BigEndian::read_u32(header)
+    };
+    let mut makernote = cmt3
+        .get((ifd_offset as usize)..)
+        .ok_or_else(|| invalid("Invalid IFD offset in CMT3"))?
+        .to_vec();
+    let mut footer = [0u8; 8];
+    if little_endian {
+        footer[..2].copy_from_slice(b"II");
+        LittleEndian::write_u16(&mut footer[2..], 42);
+        LittleEndian::write_u32(&mut footer[4..], ifd_offset);
+    } else {
+        footer[..2].copy_from_slice(b"MM");
+        BigEndian::write_u16(&mut footer[2..], 42);
+        BigEndian::write_u32(&mut footer[4..], ifd_offset);
+    }
+    makernote.extend_from_slice(&footer);
+    Ok(makernote)
+}
+
+// Combines the CMT boxes in to a single EXIF structure, so that it can be handled like a still
+fn read_canon_uuid(canon: &[u8]) -> Result<Exif, Error> {
+    let cmt1 = find_child(canon, b"CMT1")?.ok_or_else(|| invalid("Missing CMT1 box"))?;
+    let cmt2 = find_child(canon, b"CMT2")?.ok_or_else(|| invalid("Missing CMT2 box"))?;
+    let mut fields = read_cmt_fields(cmt1, Context::Tiff)?;
+    fields.extend(read_cmt_fields(cmt2, Context::Exif)?);
+    if let Some(cmt3) = find_child(canon, b"CMT3")? {
+        fields.push(Field {
+            tag: Tag::MakerNote,
+            ifd_num: In::PRIMARY,
+            value: Value::Undefined(cmt3_to_makernote(cmt3)?, 0),
+        });
+    }
+
+    let mut writer = Writer::new();
+    for field in fields.iter() {
+        writer.push_field(field);
+    }
+    let mut tiff = Cursor::new(vec![]);
+    writer.write(&mut tiff, tiff_byte_order(cmt1)?)?;
+    Ok(exif::Reader::new().read_raw(tiff.into_inner())?)
+}
+
+// Older EOS bodies write MOV files with a JPEG thumbnail in moov/udta/CNTH/CNDA, which carries the
+// same EXIF data as a still
+pub(in crate) fn read_exif<R: Read + Seek>(reader: &mut R) -> Result<Exif, Error> {
+    let moov = read_moov(reader)?;
+    for (box_type, body) in child_boxes(&moov)? {
+        if &box_type == b"uuid" && body.starts_with(&CANON_UUID) {
+            return read_canon_uuid(&body[CANON_UUID.len()..]);
+        }
+    }
+    let thumbnail = find_child(&moov, b"udta")?
+        .map(|x| find_child(x, b"CNTH"))
+        .transpose()?
+        .flatten()
+        .map(|x| find_child(x, b"CNDA"))
+        .transpose()?
+        .flatten();
+    match thumbnail {
+        Some(jpeg) => Ok(exif::Reader::new().read_from_container(&mut Cursor::new(jpeg))?),
+        None => Err(Error::Unsupported(
+            "No Canon metadata found in video file".to_string(),
+        )),
+    }
+}
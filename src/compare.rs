@@ -0,0 +1,162 @@
+use crate::metadata::ImageMetadata;
+use crate::units::{round_tenths, TemperatureUnit};
+use std::fmt::Write;
+
+struct Row {
+    label: &'static str,
+    a: String,
+    b: String,
+    // Frames which differ in this field shouldn't be used to calibrate each other
+    mismatch: bool,
+}
+
+impl Row {
+    // Never marked, since the field doesn't affect calibration
+    fn informational(label: &'static str, a: String, b: String) -> Row {
+        Row {
+            label,
+            a,
+            b,
+            mismatch: false,
+        }
+    }
+
+    // Marked if the values differ
+    fn calibration(label: &'static str, a: String, b: String) -> Row {
+        let mismatch = a != b;
+        Row {
+            label,
+            a,
+            b,
+            mismatch,
+        }
+    }
+}
+
+fn format_flag(value: Option<bool>) -> String {
+    match value {
+        Some(true) => "on".to_string(),
+        Some(false) => "off".to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+// Field by field comparison of two frames. Fields which matter when using one as a dark for the
+// other are marked with * if they differ. The temperatures only count as different if they're more
+// than max_temperature_delta apart, in the given unit. An unknown temperature is never a mismatch,
+// since it can't be checked
+pub(in crate) fn format_diff(
+    a: (&str, &ImageMetadata),
+    b: (&str, &ImageMetadata),
+    unit: TemperatureUnit,
+    max_temperature_delta: f32,
+) -> String {
+    let (a_path, a) = a;
+    let (b_path, b) = b;
+    let format_temperature = |x: f32| format!("{} {}", round_tenths(x), unit);
+    let temperature_a = a.temperature.map(|x| unit.convert(x));
+    let temperature_b = b.temperature.map(|x| unit.convert(x));
+    let delta = match (temperature_a, temperature_b) {
+        (Some(a), Some(b)) => Some(b - a),
+        _ => None,
+    };
+    let rows = vec![
+        Row::calibration("Camera", a.camera_model.clone(), b.camera_model.clone()),
+        Row::calibration(
+            "Serial number",
+            a.camera_serial_number.clone(),
+            b.camera_serial_number.clone(),
+        ),
+        Row::calibration(
+            "Sensitivity",
+            format!("{} {}", a.sensitivity_name(), a.sensor_sensitivity),
+            format!("{} {}", b.sensitivity_name(), b.sensor_sensitivity),
+        ),
+        Row::calibration(
+            "Exposure time",
+            format!("{} s", a.exposure_time.shutter_speed()),
+            format!("{} s", b.exposure_time.shutter_speed()),
+        ),
+        Row {
+            label: "Temperature",
+            a: temperature_a.map_or_else(|| "unknown".to_string(), format_temperature),
+            b: match (temperature_b, delta) {
+                (Some(x), Some(delta)) => format!(
+                    "{} ({:+} {})",
+                    format_temperature(x),
+                    round_tenths(delta),
+                    unit
+                ),
+                (x, _) => x.map_or_else(|| "unknown".to_string(), format_temperature),
+            },
+            mismatch: matches!(delta, Some(x) if x.abs() > max_temperature_delta),
+        },
+        Row::calibration(
+            "Long exposure NR",
+            format_flag(a.long_exposure_noise_reduction),
+            format_flag(b.long_exposure_noise_reduction),
+        ),
+        Row::informational(
+            "Auto ISO",
+            format_flag(a.auto_sensitivity),
+            format_flag(b.auto_sensitivity),
+        ),
+        Row::informational(
+            "Captured",
+            a.capture_time
+                .clone()
+                .unwrap_or_else(|| "unknown".to_string()),
+            b.capture_time
+                .clone()
+                .unwrap_or_else(|| "unknown".to_string()),
+        ),
+        Row::informational(
+            "Shutter count",
+            a.shutter_count
+                .map_or_else(|| "unknown".to_string(), |x| x.to_string()),
+            b.shutter_count
+                .map_or_else(|| "unknown".to_string(), |x| x.to_string()),
+        ),
+    ];
+
+    let label_width = rows.iter().map(|x| x.label.len()).max().unwrap_or(0);
+    let a_width = rows
+        .iter()
+        .map(|x| x.a.chars().count())
+        .chain(std::iter::once(a_path.chars().count()))
+        .max()
+        .unwrap_or(0);
+    let mut result = String::new();
+    writeln!(
+        result,
+        "  {:<label_width$}  {:<a_width$}  {}",
+        "",
+        a_path,
+        b_path,
+        label_width = label_width,
+        a_width = a_width
+    )
+    .unwrap();
+    for row in rows.iter() {
+        writeln!(
+            result,
+            "{} {:<label_width$}  {:<a_width$}  {}",
+            if row.mismatch { "*" } else { " " },
+            row.label,
+            row.a,
+            row.b,
+            label_width = label_width,
+            a_width = a_width
+        )
+        .unwrap();
+    }
+    let mismatches = rows.iter().filter(|x| x.mismatch).count();
+    writeln!(result).unwrap();
+    match mismatches {
+        0 => writeln!(result, "No calibration mismatches"),
+        1 => writeln!(result, "1 calibration mismatch"),
+        n => writeln!(result, "{} calibration mismatches", n),
+    }
+    .unwrap();
+    result
+}
@@ -0,0 +1,112 @@
+use crate::error::Error;
+use crate::metadata::ImageMetadata;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::fmt::Write as FmtWrite;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::Read;
+use std::path::Path;
+
+const READ_BUFFER_BYTES: usize = 1024 * 1024;
+
+// Identifies the exposure, independent of the file it's stored in, so that the same frame converted
+// to another format or with edited metadata is still found
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct ExposureKey {
+    camera_serial_number: String,
+    capture_time: String,
+    shutter_count: Option<u32>,
+    exposure_time_bits: u32,
+    sensor_sensitivity: u32,
+}
+
+pub(in crate) struct DuplicateGroups<'a> {
+    // Files with identical contents
+    pub identical: Vec<Vec<&'a str>>,
+    // Files which are different, but record the same exposure
+    pub same_exposure: Vec<Vec<&'a str>>,
+}
+
+// Length and hash of the file contents. The hash is not cryptographic, and only stable within a
+// single run
+fn hash_file<P: AsRef<Path>>(path: P) -> Result<(u64, u64), Error> {
+    let mut file = File::open(path)?;
+    let mut hasher = DefaultHasher::new();
+    let mut buffer = vec![0; READ_BUFFER_BYTES];
+    let mut length = 0;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.write(&buffer[..read]);
+        length += read as u64;
+    }
+    Ok((length, hasher.finish()))
+}
+
+fn exposure_key(metadata: &ImageMetadata) -> Option<ExposureKey> {
+    // Without a capture time, different frames of the same camera and settings can't be told apart
+    let capture_time = metadata.capture_time.clone()?;
+    Some(ExposureKey {
+        camera_serial_number: metadata.camera_serial_number.clone(),
+        capture_time,
+        shutter_count: metadata.shutter_count,
+        exposure_time_bits: metadata.exposure_time.0.to_bits(),
+        sensor_sensitivity: metadata.sensor_sensitivity,
+    })
+}
+
+fn groups<K: Ord>(keys: Vec<(K, &str)>) -> Vec<Vec<&str>> {
+    let mut grouped: BTreeMap<K, Vec<&str>> = BTreeMap::new();
+    for (key, path) in keys {
+        grouped.entry(key).or_default().push(path);
+    }
+    grouped.values().filter(|x| x.len() > 1).cloned().collect()
+}
+
+// Every path is compared by content, but only the files with metadata by exposure
+pub(in crate) fn find_duplicates<'a>(
+    paths: &[&'a str],
+    files: &[(&'a str, ImageMetadata)],
+) -> Result<DuplicateGroups<'a>, Error> {
+    let mut hashes = vec![];
+    for path in paths.iter().copied() {
+        hashes.push((hash_file(path)?, path));
+    }
+    let identical = groups(hashes);
+
+    // Only report the first copy of a set of identical files, so that they're not listed twice
+    let copies: Vec<&str> = identical.iter().flat_map(|x| x[1..].to_vec()).collect();
+    let same_exposure = groups(
+        files
+            .iter()
+            .filter(|(path, _)| !copies.contains(path))
+            .filter_map(|(path, metadata)| exposure_key(metadata).map(|x| (x, *path)))
+            .collect(),
+    );
+
+    Ok(DuplicateGroups {
+        identical,
+        same_exposure,
+    })
+}
+
+pub(in crate) fn format_duplicates(duplicates: &DuplicateGroups) -> String {
+    let mut output = String::new();
+    for (title, groups) in [
+        ("Identical files", &duplicates.identical),
+        ("Same exposure", &duplicates.same_exposure),
+    ]
+    .iter()
+    {
+        for group in groups.iter() {
+            writeln!(output, "{}:", title).unwrap();
+            for path in group.iter() {
+                writeln!(output, "  {}", path).unwrap();
+            }
+        }
+    }
+    output
+}
@@ -1,15 +1,236 @@
+use crate::tiff::{CorruptIfd, ValueTooLarge};
+use std::fmt;
 use std::io;
 
 #[derive(Debug)]
 pub(in crate) enum Error {
-    InvalidData(String),
+    // A field which the metadata requires isn't present
+    MissingField {
+        field: &'static str,
+    },
+    // A field is present, but has the wrong type or number of values
+    WrongType {
+        field: &'static str,
+        expected: &'static str,
+        actual: String,
+    },
+    // The metadata is stored in a vendor specific format, which isn't supported for this make
+    UnsupportedMake {
+        make: String,
+    },
+    // A maker note entry is larger than the configured limit
+    ValueTooLarge {
+        tag: u16,
+        bytes: usize,
+        limit: usize,
+    },
+    // An IFD or maker note is damaged, or isn't in the format expected. The offset is relative to
+    // the start of the buffer being parsed
+    CorruptIfd {
+        offset: usize,
+        reason: &'static str,
+    },
+    // The metadata wasn't found within the --max-scan-bytes limit
+    ScanLimitReached {
+        limit: u64,
+    },
+    // A field of an XMP sidecar can't be parsed
+    InvalidSidecarValue {
+        field: &'static str,
+        value: String,
+    },
     Unsupported(String),
+    // A command line argument, or an expression or template given in one, is invalid
+    InvalidArgument(String),
     Io(io::Error),
     Exif(exif::Error),
+    Xmp(roxmltree::Error),
+    #[cfg(feature = "gpl")]
+    Raw(String),
+    #[cfg(feature = "remote")]
+    Http(Box<ureq::Error>),
+    #[cfg(feature = "remote")]
+    Tls(native_tls::Error),
+    #[cfg(feature = "server")]
+    Server(Box<dyn std::error::Error + Send + Sync>),
+    #[cfg(feature = "watch")]
+    Watch(notify::Error),
+}
+
+impl Error {
+    // Identifies the kind of error in JSON output. Codes are never renumbered or reused
+    pub fn code(&self) -> u32 {
+        match self {
+            // 1 was InvalidData, which was replaced by the structured variants
+            Error::Unsupported(_) => 2,
+            Error::MissingField { .. } => 3,
+            Error::WrongType { .. } => 4,
+            Error::UnsupportedMake { .. } => 5,
+            Error::Io(_) => 6,
+            Error::Exif(_) => 7,
+            Error::Xmp(_) => 8,
+            #[cfg(feature = "gpl")]
+            Error::Raw(_) => 9,
+            #[cfg(feature = "remote")]
+            Error::Http(_) => 10,
+            #[cfg(feature = "remote")]
+            Error::Tls(_) => 11,
+            #[cfg(feature = "server")]
+            Error::Server(_) => 12,
+            #[cfg(feature = "watch")]
+            Error::Watch(_) => 13,
+            Error::InvalidArgument(_) => 14,
+            Error::ValueTooLarge { .. } => 15,
+            Error::CorruptIfd { .. } => 16,
+            Error::ScanLimitReached { .. } => 17,
+            Error::InvalidSidecarValue { .. } => 18,
+        }
+    }
+
+    // Short name of the kind of error, for summaries
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Unsupported(_) => "unsupported",
+            Error::MissingField { .. } => "missing field",
+            Error::WrongType { .. } => "wrong type",
+            Error::UnsupportedMake { .. } => "unsupported make",
+            Error::Io(_) => "I/O",
+            Error::Exif(_) => "EXIF",
+            Error::Xmp(_) => "XMP",
+            #[cfg(feature = "gpl")]
+            Error::Raw(_) => "raw decoding",
+            #[cfg(feature = "remote")]
+            Error::Http(_) => "HTTP",
+            #[cfg(feature = "remote")]
+            Error::Tls(_) => "TLS",
+            #[cfg(feature = "server")]
+            Error::Server(_) => "server",
+            #[cfg(feature = "watch")]
+            Error::Watch(_) => "watch",
+            Error::InvalidArgument(_) => "invalid argument",
+            Error::ValueTooLarge { .. } => "value too large",
+            Error::CorruptIfd { .. } => "corrupt IFD",
+            Error::ScanLimitReached { .. } => "scan limit reached",
+            Error::InvalidSidecarValue { .. } => "invalid sidecar value",
+        }
+    }
+
+    // Exit status of the CLI when this error occurs. These are documented in the --help output
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Unsupported(_)
+            | Error::UnsupportedMake { .. }
+            | Error::Exif(exif::Error::NotSupported(_)) => 2,
+            Error::Io(err) | Error::Exif(exif::Error::Io(err)) if is_corrupt_data(err) => 3,
+            Error::Io(_) | Error::Exif(exif::Error::Io(_)) => 4,
+            Error::ScanLimitReached { .. } => 5,
+            Error::MissingField { .. }
+            | Error::WrongType { .. }
+            | Error::ValueTooLarge { .. }
+            | Error::CorruptIfd { .. }
+            | Error::InvalidSidecarValue { .. }
+            | Error::Exif(_)
+            | Error::Xmp(_) => 3,
+            _ => 1,
+        }
+    }
+}
+
+// Parsers report damaged or truncated files with these kinds, whereas the others come from the OS,
+// such as NotFound or PermissionDenied
+fn is_corrupt_data(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput | io::ErrorKind::UnexpectedEof
+    )
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::MissingField { field } => write!(f, "Missing {} field", field),
+            Error::WrongType {
+                field,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Expected {} for {} field, found {}",
+                expected, field, actual
+            ),
+            Error::UnsupportedMake { make } => write!(f, "{} cameras are not supported", make),
+            Error::ValueTooLarge { tag, bytes, limit } => write!(
+                f,
+                "Maker note entry {:#06x} is {} bytes, which is more than the limit of {}",
+                tag, bytes, limit
+            ),
+            Error::CorruptIfd { offset, reason } => {
+                write!(f, "Corrupt IFD at offset {}: {}", offset, reason)
+            }
+            Error::ScanLimitReached { limit } => write!(
+                f,
+                "Stopped reading after the first {} bytes, which is the --max-scan-bytes limit",
+                limit
+            ),
+            Error::InvalidSidecarValue { field, value } => {
+                write!(f, "Bad {} value in XMP sidecar: {}", field, value)
+            }
+            Error::Unsupported(message) | Error::InvalidArgument(message) => {
+                write!(f, "{}", message)
+            }
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::Exif(err) => write!(f, "EXIF error: {}", err),
+            Error::Xmp(err) => write!(f, "XMP error: {}", err),
+            #[cfg(feature = "gpl")]
+            Error::Raw(message) => write!(f, "Raw decoding error: {}", message),
+            #[cfg(feature = "remote")]
+            Error::Http(err) => write!(f, "HTTP error: {}", err),
+            #[cfg(feature = "remote")]
+            Error::Tls(err) => write!(f, "TLS error: {}", err),
+            #[cfg(feature = "server")]
+            Error::Server(err) => write!(f, "Server error: {}", err),
+            #[cfg(feature = "watch")]
+            Error::Watch(err) => write!(f, "Watch error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Exif(err) => Some(err),
+            Error::Xmp(err) => Some(err),
+            #[cfg(feature = "remote")]
+            Error::Http(err) => Some(err.as_ref()),
+            #[cfg(feature = "remote")]
+            Error::Tls(err) => Some(err),
+            #[cfg(feature = "server")]
+            Error::Server(err) => Some(err.as_ref()),
+            #[cfg(feature = "watch")]
+            Error::Watch(err) => Some(err),
+            _ => None,
+        }
+    }
 }
 
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Error {
+        if let Some(x) = err.get_ref() {
+            if let Some(x) = x.downcast_ref::<ValueTooLarge>() {
+                return Error::ValueTooLarge {
+                    tag: x.tag,
+                    bytes: x.bytes,
+                    limit: x.limit,
+                };
+            }
+            if let Some(x) = x.downcast_ref::<CorruptIfd>() {
+                return Error::CorruptIfd {
+                    offset: x.offset,
+                    reason: x.reason,
+                };
+            }
+        }
         Error::Io(err)
     }
 }
@@ -19,3 +240,44 @@ impl From<exif::Error> for Error {
         Error::Exif(err)
     }
 }
+
+impl From<roxmltree::Error> for Error {
+    fn from(err: roxmltree::Error) -> Error {
+        Error::Xmp(err)
+    }
+}
+
+#[cfg(feature = "remote")]
+impl From<Box<ureq::Error>> for Error {
+    fn from(err: Box<ureq::Error>) -> Error {
+        Error::Http(err)
+    }
+}
+
+#[cfg(feature = "remote")]
+impl From<native_tls::Error> for Error {
+    fn from(err: native_tls::Error) -> Error {
+        Error::Tls(err)
+    }
+}
+
+#[cfg(feature = "server")]
+impl From<Box<dyn std::error::Error + Send + Sync>> for Error {
+    fn from(err: Box<dyn std::error::Error + Send + Sync>) -> Error {
+        Error::Server(err)
+    }
+}
+
+#[cfg(feature = "server")]
+impl From<ctrlc::Error> for Error {
+    fn from(err: ctrlc::Error) -> Error {
+        Error::Server(Box::new(err))
+    }
+}
+
+#[cfg(feature = "watch")]
+impl From<notify::Error> for Error {
+    fn from(err: notify::Error) -> Error {
+        Error::Watch(err)
+    }
+}
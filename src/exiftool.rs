@@ -0,0 +1,57 @@
+use crate::metadata::ImageMetadata;
+use crate::units::{round_tenths, Seconds};
+use serde_json::{json, Value};
+
+// Matches exiftool's print conversion for ExposureTime
+fn format_exposure_time(exposure_time: Seconds) -> Value {
+    if exposure_time.0 > 0.0 && exposure_time.0 < 0.25001 {
+        json!(format!("1/{}", exposure_time.reciprocal()))
+    } else {
+        let rounded = round_tenths(exposure_time.0);
+        if rounded.fract() == 0.0 {
+            json!(rounded as u64)
+        } else {
+            json!(rounded)
+        }
+    }
+}
+
+fn to_json(source_file: &str, metadata: &ImageMetadata) -> Value {
+    let mut fields = json!({
+        "SourceFile": source_file,
+        "Make": metadata.camera_make,
+        "Model": metadata.camera_model,
+        "SerialNumber": metadata.camera_serial_number,
+        "ISO": metadata.sensor_sensitivity,
+        "ExposureTime": format_exposure_time(metadata.exposure_time),
+    });
+    if let Some(temperature) = metadata.temperature {
+        fields["CameraTemperature"] = json!(format!("{} C", temperature));
+    }
+    if let Some(temperature) = metadata.secondary_temperature {
+        fields["CameraTemperature2"] = json!(format!("{} C", temperature));
+    }
+    if let Some(capture_time) = &metadata.capture_time {
+        // exiftool uses the EXIF date format
+        fields["DateTimeOriginal"] =
+            json!(capture_time.replacen('-', ":", 2).replacen('T', " ", 1));
+    }
+    if let Some(shutter_count) = metadata.shutter_count {
+        fields["ShutterCount"] = json!(shutter_count);
+    }
+    if let Some(thumbnail) = &metadata.thumbnail {
+        fields["ThumbnailOffset"] = json!(thumbnail.offset);
+        fields["ThumbnailLength"] = json!(thumbnail.length);
+    }
+    fields
+}
+
+// Formats the metadata like `exiftool -json` does, so that existing scripts can switch over
+// without changes
+pub(in crate) fn format_json(files: &[(&str, ImageMetadata)]) -> String {
+    let values: Vec<Value> = files
+        .iter()
+        .map(|(source_file, metadata)| to_json(source_file, metadata))
+        .collect();
+    serde_json::to_string_pretty(&values).unwrap()
+}
@@ -0,0 +1,517 @@
+use crate::error::Error;
+use crate::metadata::{Confidence, ImageMetadata};
+use crate::units::{Fraction, TemperatureUnit};
+use std::cmp::Ordering;
+
+// Relative tolerance of == and != on numbers, so that exposure==1/30 matches the rounded value
+// stored by the camera
+const EQUALITY_TOLERANCE: f64 = 1e-3;
+
+#[derive(Clone, Copy, PartialEq)]
+pub(in crate) enum Field {
+    Temperature,
+    Iso,
+    Exposure,
+    Serial,
+    Model,
+    DateTime,
+    Lenr,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(in crate) enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+pub(in crate) enum Operand {
+    Number(f64),
+    // Integer fractions, like 1/8000, which are compared exactly against rational exposure times
+    Fraction(Fraction),
+    Text(String),
+    Bool(bool),
+}
+
+impl Operand {
+    fn number(&self) -> Option<f64> {
+        match self {
+            Operand::Number(x) => Some(*x),
+            Operand::Fraction(x) => Some(x.to_f64()),
+            Operand::Text(_) | Operand::Bool(_) => None,
+        }
+    }
+}
+
+// Boolean expression over the metadata, such as "temp>=18 && iso==1600". Supports the fields
+// temp, iso, exposure, serial, model, datetime, and lenr (true or false), the comparisons
+// == != < <= > >=, !, && and ||, and parentheses. ! binds tightest, then &&, then ||. Temperatures
+// are converted to Celsius when parsed
+pub(in crate) enum Expression {
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+    Compare(Field, Op, Operand),
+}
+
+pub(in crate) struct Filter {
+    expression: Expression,
+    // Estimated values are only compared if this is set. Otherwise they're treated as unknown, so
+    // that a guess never silently decides which frames are used
+    allow_estimated: bool,
+}
+
+#[derive(Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Op(Op),
+    Word(String),
+    Quoted(String),
+}
+
+fn invalid(expression: &str, message: &str) -> Error {
+    Error::InvalidArgument(format!("Invalid filter \"{}\": {}", expression, message))
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = vec![];
+    let mut chars = expression.chars().peekable();
+    while let Some(c) = chars.next() {
+        let next = chars.peek().copied();
+        let token = match (c, next) {
+            (x, _) if x.is_whitespace() => continue,
+            ('(', _) => Token::Open,
+            (')', _) => Token::Close,
+            ('&', Some('&'))
+            | ('|', Some('|'))
+            | ('=', Some('='))
+            | ('!', Some('='))
+            | ('<', Some('='))
+            | ('>', Some('=')) => {
+                chars.next();
+                match c {
+                    '&' => Token::And,
+                    '|' => Token::Or,
+                    '=' => Token::Op(Op::Eq),
+                    '!' => Token::Op(Op::Ne),
+                    '<' => Token::Op(Op::Le),
+                    _ => Token::Op(Op::Ge),
+                }
+            }
+            ('!', _) => Token::Not,
+            ('<', _) => Token::Op(Op::Lt),
+            ('>', _) => Token::Op(Op::Gt),
+            ('"', _) => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(x) => text.push(x),
+                        None => return Err(invalid(expression, "unterminated string")),
+                    }
+                }
+                Token::Quoted(text)
+            }
+            (x, _) if x.is_alphanumeric() || "_-+.:/".contains(x) => {
+                let mut word = x.to_string();
+                while let Some(x) = chars.peek().copied() {
+                    if !(x.is_alphanumeric() || "_-+.:/".contains(x)) {
+                        break;
+                    }
+                    word.push(x);
+                    chars.next();
+                }
+                Token::Word(word)
+            }
+            (x, _) => {
+                return Err(invalid(
+                    expression,
+                    &format!("unexpected character '{}'", x),
+                ))
+            }
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+// Numbers may be written as a fraction, like 1/30
+fn parse_number(value: &str) -> Option<f64> {
+    let mut parts = value.splitn(2, '/');
+    let numerator = parts.next()?.parse::<f64>().ok()?;
+    match parts.next() {
+        Some(x) => {
+            let denominator = x.parse::<f64>().ok()?;
+            if denominator == 0.0 {
+                None
+            } else {
+                Some(numerator / denominator)
+            }
+        }
+        None => Some(numerator),
+    }
+}
+
+struct Parser<'a> {
+    expression: &'a str,
+    unit: TemperatureUnit,
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn or(&mut self) -> Result<Expression, Error> {
+        let mut filter = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            filter = Expression::Or(Box::new(filter), Box::new(self.and()?));
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> Result<Expression, Error> {
+        let mut filter = self.not()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            filter = Expression::And(Box::new(filter), Box::new(self.not()?));
+        }
+        Ok(filter)
+    }
+
+    fn not(&mut self) -> Result<Expression, Error> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            return Ok(Expression::Not(Box::new(self.not()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expression, Error> {
+        let field = match self.next() {
+            Some(Token::Open) => {
+                let filter = self.or()?;
+                if self.next() != Some(Token::Close) {
+                    return Err(invalid(self.expression, "missing )"));
+                }
+                return Ok(filter);
+            }
+            Some(Token::Word(x)) => match x.as_str() {
+                "temp" => Field::Temperature,
+                "iso" => Field::Iso,
+                "exposure" => Field::Exposure,
+                "serial" => Field::Serial,
+                "model" => Field::Model,
+                "datetime" => Field::DateTime,
+                "lenr" => Field::Lenr,
+                _ => return Err(invalid(self.expression, &format!("unknown field {}", x))),
+            },
+            _ => return Err(invalid(self.expression, "expected a field")),
+        };
+        let op = match self.next() {
+            Some(Token::Op(x)) => x,
+            _ => return Err(invalid(self.expression, "expected a comparison")),
+        };
+        let value = match self.next() {
+            Some(Token::Word(x)) | Some(Token::Quoted(x)) => x,
+            _ => return Err(invalid(self.expression, "expected a value")),
+        };
+        let operand = match field {
+            Field::Temperature | Field::Iso | Field::Exposure => match Fraction::parse(&value) {
+                Some(x) if value.contains('/') => Operand::Fraction(x),
+                _ => Operand::Number(parse_number(&value).ok_or_else(|| {
+                    invalid(self.expression, &format!("{} is not a number", value))
+                })?),
+            },
+            Field::Serial | Field::Model | Field::DateTime => Operand::Text(value),
+            Field::Lenr => Operand::Bool(match value.as_str() {
+                "true" | "on" => true,
+                "false" | "off" => false,
+                _ => {
+                    return Err(invalid(
+                        self.expression,
+                        &format!("{} is not true or false", value),
+                    ))
+                }
+            }),
+        };
+        let operand = match (field, operand.number()) {
+            (Field::Temperature, Some(x)) => {
+                Operand::Number(f64::from(self.unit.to_celsius(x as f32).0))
+            }
+            _ => operand,
+        };
+        Ok(Expression::Compare(field, op, operand))
+    }
+}
+
+impl Filter {
+    pub fn parse(
+        expression: &str,
+        unit: TemperatureUnit,
+        allow_estimated: bool,
+    ) -> Result<Filter, Error> {
+        let mut parser = Parser {
+            expression,
+            unit,
+            tokens: tokenize(expression)?,
+            position: 0,
+        };
+        let filter = parser.or()?;
+        if parser.peek().is_some() {
+            return Err(invalid(expression, "unexpected trailing input"));
+        }
+        Ok(Filter {
+            expression: filter,
+            allow_estimated,
+        })
+    }
+
+    pub fn matches(&self, metadata: &ImageMetadata) -> bool {
+        self.expression.evaluate(metadata, self.allow_estimated) == Some(true)
+    }
+}
+
+impl Expression {
+    // None if the result depends on a value which is unknown, or estimated and not allowed. Like
+    // NULL in SQL, so that negating a comparison doesn't make frames without the value match
+    fn evaluate(&self, metadata: &ImageMetadata, allow_estimated: bool) -> Option<bool> {
+        match self {
+            Expression::And(a, b) => match (
+                a.evaluate(metadata, allow_estimated),
+                b.evaluate(metadata, allow_estimated),
+            ) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            },
+            Expression::Or(a, b) => match (
+                a.evaluate(metadata, allow_estimated),
+                b.evaluate(metadata, allow_estimated),
+            ) {
+                (Some(true), _) | (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None,
+            },
+            Expression::Not(x) => x.evaluate(metadata, allow_estimated).map(|x| !x),
+            Expression::Compare(field, op, operand) => {
+                let confidence = &metadata.confidence;
+                let estimated = match field {
+                    Field::Temperature => confidence.temperature,
+                    Field::Iso => confidence.sensor_sensitivity,
+                    Field::Exposure => confidence.exposure_time,
+                    Field::Serial => confidence.camera_serial_number,
+                    Field::Model => confidence.camera_model,
+                    Field::DateTime => confidence.capture_time,
+                    Field::Lenr => Confidence::Exact,
+                } == Confidence::Estimated;
+                if estimated && !allow_estimated {
+                    return None;
+                }
+                let ordering = match (field, operand) {
+                    (Field::Exposure, Operand::Fraction(x)) => {
+                        match metadata.exposure_time_fraction {
+                            Some(y) => Some(y.cmp(x)),
+                            None => {
+                                compare_numbers(f64::from(metadata.exposure_time.0), x.to_f64())
+                            }
+                        }
+                    }
+                    // Frames without a temperature never match
+                    (Field::Temperature, _) => match (metadata.temperature, operand.number()) {
+                        (Some(x), Some(y)) => compare_numbers(f64::from(x.0), y),
+                        _ => None,
+                    },
+                    (Field::Iso, _) => operand
+                        .number()
+                        .and_then(|x| compare_numbers(f64::from(metadata.sensor_sensitivity), x)),
+                    (Field::Exposure, _) => operand
+                        .number()
+                        .and_then(|x| compare_numbers(f64::from(metadata.exposure_time.0), x)),
+                    (Field::Serial, Operand::Text(x)) => {
+                        Some(metadata.camera_serial_number.as_str().cmp(x))
+                    }
+                    (Field::Model, Operand::Text(x)) => Some(metadata.camera_model.as_str().cmp(x)),
+                    // Frames without a capture time never match
+                    (Field::DateTime, Operand::Text(x)) => {
+                        metadata.capture_time.as_deref().map(|y| y.cmp(x))
+                    }
+                    // Frames where it isn't known never match
+                    (Field::Lenr, Operand::Bool(x)) => {
+                        metadata.long_exposure_noise_reduction.map(|y| y.cmp(x))
+                    }
+                    _ => None,
+                };
+                ordering.map(|ordering| match op {
+                    Op::Eq => ordering == Ordering::Equal,
+                    Op::Ne => ordering != Ordering::Equal,
+                    Op::Lt => ordering == Ordering::Less,
+                    Op::Le => ordering != Ordering::Greater,
+                    Op::Gt => ordering == Ordering::Greater,
+                    Op::Ge => ordering != Ordering::Less,
+                })
+            }
+        }
+    }
+}
+
+// Purely relative, so that short exposures like 1/8000 aren't equal to their neighbours. A
+// reference of 0 only matches exactly
+fn compare_numbers(value: f64, reference: f64) -> Option<Ordering> {
+    if (value - reference).abs() <= EQUALITY_TOLERANCE * reference.abs() {
+        Some(Ordering::Equal)
+    } else {
+        value.partial_cmp(&reference)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::{Celsius, Seconds};
+    use serde_json::json;
+
+    // ISO 800, 1/30 s and 20 C, all read exactly
+    fn metadata() -> ImageMetadata {
+        serde_json::from_value(json!({
+            "camera_make": "Canon",
+            "camera_model": "Canon EOS 6D",
+            "camera_serial_number": "123",
+            "sensor_sensitivity": 800,
+            "sensitivity_type": 2,
+            "sensitivities": {},
+            "exposure_time": 0.033333335,
+            "exposure_time_fraction": "1/30",
+            "temperature": 20.0,
+            "confidence": {
+                "camera_model": "exact",
+                "camera_serial_number": "exact",
+                "sensor_sensitivity": "exact",
+                "exposure_time": "exact",
+                "temperature": "exact",
+                "capture_time": "exact"
+            },
+            "warnings": []
+        }))
+        .unwrap()
+    }
+
+    fn matches(expression: &str, metadata: &ImageMetadata) -> bool {
+        Filter::parse(expression, TemperatureUnit::Celsius, false)
+            .unwrap()
+            .matches(metadata)
+    }
+
+    #[test]
+    fn precedence() {
+        let metadata = metadata();
+        // && binds tighter than ||
+        assert!(matches("iso==800 || iso==1600 && temp>30", &metadata));
+        assert!(!matches("(iso==800 || iso==1600) && temp>30", &metadata));
+        assert!(matches("temp>30 && iso==1600 || iso==800", &metadata));
+        // ! binds tighter than &&
+        assert!(!matches("!iso==800 && temp==20", &metadata));
+        assert!(matches("!(iso==800 && temp>30)", &metadata));
+        assert!(matches("!!iso==800", &metadata));
+    }
+
+    #[test]
+    fn exposure_fractions_are_exact() {
+        let mut metadata = metadata();
+        assert!(matches("exposure==1/30", &metadata));
+        assert!(matches("exposure==2/60", &metadata));
+        assert!(!matches("exposure==1/32", &metadata));
+        assert!(matches("exposure<1/25 && exposure>1/40", &metadata));
+
+        // 1/8000 and 1/8001 differ by less than the tolerance of decimal comparisons
+        metadata.exposure_time = Seconds(1.0 / 8000.0);
+        metadata.exposure_time_fraction = Fraction::new(1, 8000);
+        assert!(matches("exposure==1/8000", &metadata));
+        assert!(!matches("exposure==1/8001", &metadata));
+
+        // Without a rational exposure time, fractions are compared with the tolerance
+        metadata.exposure_time_fraction = None;
+        assert!(matches("exposure==1/8000", &metadata));
+        assert!(matches("exposure==0.000125", &metadata));
+        assert!(!matches("exposure==1/4000", &metadata));
+    }
+
+    #[test]
+    fn unknown_temperature_never_matches() {
+        let mut metadata = metadata();
+        metadata.temperature = None;
+        for expression in ["temp==20", "temp!=20", "!(temp==20)", "!(temp!=20)"].iter() {
+            assert!(!matches(expression, &metadata), "{}", expression);
+        }
+        assert!(matches("temp==20 || iso==800", &metadata));
+        assert!(!matches("temp==20 || iso==1600", &metadata));
+    }
+
+    #[test]
+    fn estimated_values_need_allow_estimated() {
+        let mut metadata = metadata();
+        metadata.confidence.temperature = Confidence::Estimated;
+        for expression in ["temp==20", "temp!=25", "!(temp==25)"].iter() {
+            let strict = Filter::parse(expression, TemperatureUnit::Celsius, false).unwrap();
+            assert!(!strict.matches(&metadata), "{}", expression);
+            let allowed = Filter::parse(expression, TemperatureUnit::Celsius, true).unwrap();
+            assert!(allowed.matches(&metadata), "{}", expression);
+        }
+        // Other fields aren't affected
+        assert!(matches("iso==800", &metadata));
+    }
+
+    #[test]
+    fn temperatures_are_read_in_the_given_unit() {
+        let mut metadata = metadata();
+        metadata.temperature = Some(Celsius(20.0));
+        let filter = Filter::parse("temp==68", TemperatureUnit::Fahrenheit, false).unwrap();
+        assert!(filter.matches(&metadata));
+        let filter = Filter::parse("temp>293 && temp<294", TemperatureUnit::Kelvin, false).unwrap();
+        assert!(filter.matches(&metadata));
+        assert!(!matches("temp==68", &metadata));
+    }
+
+    #[test]
+    fn parse_errors() {
+        for expression in [
+            "",
+            "temp",
+            "temp>=",
+            "foo==1",
+            "iso==abc",
+            "iso==1/0",
+            "lenr==maybe",
+            "(iso==800",
+            "iso==800)",
+            "iso==800 iso==1600",
+            "model==\"EOS",
+            "iso # 800",
+            "iso==800 &&",
+            "!",
+        ]
+        .iter()
+        {
+            let err = Filter::parse(expression, TemperatureUnit::Celsius, false)
+                .err()
+                .unwrap_or_else(|| panic!("{} parsed", expression));
+            assert!(matches!(err, Error::InvalidArgument(_)), "{}", expression);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use crate::metadata::ImageMetadata;
+
+// Header records ("cards") are always 80 ASCII characters
+const CARD_WIDTH: usize = 80;
+// Headers and data are each padded to a multiple of this many bytes
+#[cfg(feature = "gpl")]
+pub(in crate) const FITS_BLOCK_SIZE: usize = 2880;
+
+fn card(keyword: &str, value: &str, comment: &str) -> String {
+    let card = format!("{:<8}= {} / {}", keyword, value, comment);
+    format!(
+        "{:<width$}",
+        &card[..card.len().min(CARD_WIDTH)],
+        width = CARD_WIDTH
+    )
+}
+
+// Fixed format: numbers are right justified to column 30
+fn number_card<T: std::fmt::Debug>(keyword: &str, value: T, comment: &str) -> String {
+    card(keyword, &format!("{:>20?}", value), comment)
+}
+
+// Fixed format: strings start at column 11, are padded to at least 8 characters, and use doubled
+// single quotes as the escape for a quote
+fn string_card(keyword: &str, value: &str, comment: &str) -> String {
+    let ascii: String = value
+        .chars()
+        .map(|x| {
+            if x.is_ascii() && !x.is_ascii_control() {
+                x
+            } else {
+                '?'
+            }
+        })
+        .collect();
+    let quoted = format!("'{:<8}'", ascii.replace('\'', "''"));
+    card(keyword, &format!("{:<20}", quoted), comment)
+}
+
+fn end_card() -> String {
+    format!("{:<width$}", "END", width = CARD_WIDTH)
+}
+
+fn metadata_cards(metadata: &ImageMetadata) -> Vec<String> {
+    let mut cards = vec![
+        number_card("EXPTIME", metadata.exposure_time, "[s] Exposure duration"),
+        number_card("ISOSPEED", metadata.sensor_sensitivity, "ISO speed"),
+        string_card("INSTRUME", &metadata.camera_model, "Camera model"),
+    ];
+    if let Some(temperature) = metadata.temperature {
+        cards.push(number_card(
+            "CCD-TEMP",
+            temperature,
+            "[C] Sensor temperature",
+        ));
+    }
+    if let Some(capture_time) = &metadata.capture_time {
+        cards.push(string_card("DATE-OBS", capture_time, "Start of exposure"));
+    }
+    cards
+}
+
+// Format the metadata as FITS header keywords, as consumed by PixInsight, astropy, etc
+pub(in crate) fn format_header(metadata: &ImageMetadata) -> String {
+    let mut cards = metadata_cards(metadata);
+    cards.push(end_card());
+
+    let mut header = cards.join("\n");
+    header.push('\n');
+    header
+}
+
+// Header of a FITS file holding a single 2D image, padded to a whole block. BITPIX is 16 for
+// unsigned 16-bit data, which FITS stores as signed with an offset of BZERO, or -32 for floats
+#[cfg(feature = "gpl")]
+pub(in crate) fn image_header(
+    bitpix: i32,
+    width: usize,
+    height: usize,
+    combined: usize,
+    metadata: Option<&ImageMetadata>,
+) -> Vec<u8> {
+    let mut cards = vec![
+        card(
+            "SIMPLE",
+            &format!("{:>20}", "T"),
+            "Conforms to the FITS standard",
+        ),
+        number_card("BITPIX", bitpix, "Bits per data value"),
+        number_card("NAXIS", 2, "Number of axes"),
+        number_card("NAXIS1", width, "Image width"),
+        number_card("NAXIS2", height, "Image height"),
+    ];
+    if bitpix == 16 {
+        cards.push(number_card("BZERO", 32768, "Offset of unsigned data"));
+        cards.push(number_card("BSCALE", 1, "Data scaling"));
+    }
+    cards.push(string_card("ROWORDER", "TOP-DOWN", "Order of the rows"));
+    cards.push(string_card("IMAGETYP", "Master Dark", "Type of image"));
+    cards.push(number_card(
+        "NCOMBINE",
+        combined,
+        "Number of frames combined",
+    ));
+    if let Some(metadata) = metadata {
+        cards.extend(metadata_cards(metadata));
+    }
+    cards.push(end_card());
+
+    let mut header = cards.concat().into_bytes();
+    let padding = (FITS_BLOCK_SIZE - header.len() % FITS_BLOCK_SIZE) % FITS_BLOCK_SIZE;
+    header.resize(header.len() + padding, b' ');
+    header
+}
@@ -0,0 +1,298 @@
+use byteorder::{BigEndian, ReadBytesExt};
+use std::convert::TryFrom;
+use std::io;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+// Brands which indicate a HEIF image. "mif1" should always be present, but some cameras only list
+// the codec specific brands
+const HEIF_BRANDS: &[&[u8; 4]] = &[b"mif1", b"msf1", b"heic", b"heix", b"hevc", b"avif"];
+const MAX_FTYP_BYTES: u64 = 4096;
+const MAX_META_BYTES: u64 = 16 * 1024 * 1024;
+// kamadak-exif limits the EXIF item to 64 KiB, which is too small for the maker notes written by
+// some Canon bodies
+const MAX_EXIF_BYTES: usize = 16 * 1024 * 1024;
+
+const CONSTRUCTION_METHOD_FILE: u16 = 0;
+const CONSTRUCTION_METHOD_IDAT: u16 = 1;
+
+struct Extent {
+    offset: u64,
+    length: u64,
+}
+
+struct ItemLocation {
+    construction_method: u16,
+    extents: Vec<Extent>,
+}
+
+pub(in crate) fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Returns the box type and the size of its body, or None if it extends to the end of the file
+pub(in crate) fn read_box_header<R: Read>(reader: &mut R) -> io::Result<([u8; 4], Option<u64>)> {
+    let size = reader.read_u32::<BigEndian>()?;
+    let mut box_type = [0u8; 4];
+    reader.read_exact(&mut box_type)?;
+    let body_size = match size {
+        0 => None,
+        1 => Some(
+            reader
+                .read_u64::<BigEndian>()?
+                .checked_sub(16)
+                .ok_or_else(|| invalid("Invalid box size"))?,
+        ),
+        x => Some(
+            u64::from(x)
+                .checked_sub(8)
+                .ok_or_else(|| invalid("Invalid box size"))?,
+        ),
+    };
+    Ok((box_type, body_size))
+}
+
+pub(in crate) fn read_body<R: Read>(
+    reader: &mut R,
+    size: Option<u64>,
+    limit: u64,
+) -> io::Result<Vec<u8>> {
+    let mut body = vec![];
+    match size {
+        Some(x) if x > limit => return Err(invalid("Box is too large")),
+        Some(x) => {
+            body.resize(x as usize, 0);
+            reader.read_exact(&mut body)?;
+        }
+        None => {
+            reader.take(limit + 1).read_to_end(&mut body)?;
+            if body.len() as u64 > limit {
+                return Err(invalid("Box is too large"));
+            }
+        }
+    }
+    Ok(body)
+}
+
+// Splits the contents of a box in to its child boxes
+pub(in crate) fn child_boxes(mut data: &[u8]) -> io::Result<Vec<([u8; 4], &[u8])>> {
+    let mut children = vec![];
+    while !data.is_empty() {
+        let mut cursor = Cursor::new(data);
+        let (box_type, size) = read_box_header(&mut cursor)?;
+        let start = cursor.position() as usize;
+        let end = match size {
+            Some(x) => start
+                .checked_add(x as usize)
+                .filter(|x| *x <= data.len())
+                .ok_or_else(|| invalid("Box extends past its parent"))?,
+            None => data.len(),
+        };
+        children.push((box_type, &data[start..end]));
+        data = &data[end..];
+    }
+    Ok(children)
+}
+
+// Reads an unsigned integer of 0, 4, or 8 bytes, as used by the ItemLocationBox
+fn read_sized(cursor: &mut Cursor<&[u8]>, size: u8) -> io::Result<u64> {
+    match size {
+        0 => Ok(0),
+        4 => Ok(u64::from(cursor.read_u32::<BigEndian>()?)),
+        8 => cursor.read_u64::<BigEndian>(),
+        _ => Err(invalid("Invalid field size in ItemLocationBox")),
+    }
+}
+
+fn has_heif_brand<R: Read>(reader: &mut R) -> io::Result<bool> {
+    let (box_type, size) = read_box_header(reader)?;
+    if &box_type != b"ftyp" {
+        return Ok(false);
+    }
+    let body = read_body(reader, size, MAX_FTYP_BYTES)?;
+    // The major brand, minor version, and then the compatible brands
+    Ok(body
+        .chunks_exact(4)
+        .enumerate()
+        .filter(|(i, _)| *i != 1)
+        .any(|(_, brand)| HEIF_BRANDS.iter().any(|x| &x[..] == brand)))
+}
+
+// Checks the ftyp box, and rewinds the reader to the start of the file
+pub(in crate) fn is_heif<R: Read + Seek>(reader: &mut R) -> io::Result<bool> {
+    let result = has_heif_brand(reader);
+    reader.seek(SeekFrom::Start(0))?;
+    match result {
+        Ok(x) => Ok(x),
+        Err(err)
+            if err.kind() == io::ErrorKind::UnexpectedEof
+                || err.kind() == io::ErrorKind::InvalidData =>
+        {
+            Ok(false)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// Returns the ID of the item with type "Exif"
+fn find_exif_item(iinf: &[u8]) -> io::Result<Option<u32>> {
+    let mut cursor = Cursor::new(iinf);
+    let version = cursor.read_u8()?;
+    cursor.set_position(4);
+    if version == 0 {
+        cursor.read_u16::<BigEndian>()?;
+    } else {
+        cursor.read_u32::<BigEndian>()?;
+    }
+    let entries = &iinf[cursor.position() as usize..];
+    for (box_type, infe) in child_boxes(entries)? {
+        if &box_type != b"infe" {
+            continue;
+        }
+        let mut cursor = Cursor::new(infe);
+        let version = cursor.read_u8()?;
+        cursor.set_position(4);
+        // Item types were only added in version 2
+        let item_id = match version {
+            2 => u32::from(cursor.read_u16::<BigEndian>()?),
+            3 => cursor.read_u32::<BigEndian>()?,
+            _ => continue,
+        };
+        let _protection_index = cursor.read_u16::<BigEndian>()?;
+        let mut item_type = [0u8; 4];
+        cursor.read_exact(&mut item_type)?;
+        if &item_type == b"Exif" {
+            return Ok(Some(item_id));
+        }
+    }
+    Ok(None)
+}
+
+fn find_item_location(iloc: &[u8], item_id: u32) -> io::Result<Option<ItemLocation>> {
+    let mut cursor = Cursor::new(iloc);
+    let version = cursor.read_u8()?;
+    cursor.set_position(4);
+    let sizes = cursor.read_u8()?;
+    let (offset_size, length_size) = (sizes >> 4, sizes & 0xf);
+    let sizes = cursor.read_u8()?;
+    let base_offset_size = sizes >> 4;
+    let index_size = if version == 1 || version == 2 {
+        sizes & 0xf
+    } else {
+        0
+    };
+    let item_count = if version < 2 {
+        u32::from(cursor.read_u16::<BigEndian>()?)
+    } else {
+        cursor.read_u32::<BigEndian>()?
+    };
+
+    for _ in 0..item_count {
+        let id = if version < 2 {
+            u32::from(cursor.read_u16::<BigEndian>()?)
+        } else {
+            cursor.read_u32::<BigEndian>()?
+        };
+        let construction_method = if version == 1 || version == 2 {
+            cursor.read_u16::<BigEndian>()? & 0xf
+        } else {
+            CONSTRUCTION_METHOD_FILE
+        };
+        let _data_reference_index = cursor.read_u16::<BigEndian>()?;
+        let base_offset = read_sized(&mut cursor, base_offset_size)?;
+        let extent_count = cursor.read_u16::<BigEndian>()?;
+        let mut extents = vec![];
+        for _ in 0..extent_count {
+            read_sized(&mut cursor, index_size)?;
+            let offset = read_sized(&mut cursor, offset_size)?;
+            let length = read_sized(&mut cursor, length_size)?;
+            extents.push(Extent {
+                offset: base_offset
+                    .checked_add(offset)
+                    .ok_or_else(|| invalid("Invalid extent offset"))?,
+                length,
+            });
+        }
+        if id == item_id {
+            return Ok(Some(ItemLocation {
+                construction_method,
+                extents,
+            }));
+        }
+    }
+    Ok(None)
+}
+
+fn read_extents<R: Read + Seek>(
+    reader: &mut R,
+    location: &ItemLocation,
+    idat: Option<&[u8]>,
+) -> io::Result<Vec<u8>> {
+    let mut data = vec![];
+    for extent in location.extents.iter() {
+        if extent.length == 0 || extent.length > MAX_EXIF_BYTES as u64 {
+            return Err(invalid("Unsupported EXIF item length"));
+        }
+        let length = extent.length as usize;
+        match location.construction_method {
+            CONSTRUCTION_METHOD_FILE => {
+                reader.seek(SeekFrom::Start(extent.offset))?;
+                let start = data.len();
+                data.resize(start + length, 0);
+                reader.read_exact(&mut data[start..])?;
+            }
+            CONSTRUCTION_METHOD_IDAT => {
+                let idat = idat.ok_or_else(|| invalid("Missing ItemDataBox"))?;
+                let start = extent.offset as usize;
+                let chunk = start
+                    .checked_add(length)
+                    .and_then(|end| idat.get(start..end))
+                    .ok_or_else(|| invalid("EXIF item is outside of ItemDataBox"))?;
+                data.extend_from_slice(chunk);
+            }
+            _ => return Err(invalid("Unsupported item construction method")),
+        }
+        if data.len() > MAX_EXIF_BYTES {
+            return Err(invalid("EXIF item is too large"));
+        }
+    }
+    Ok(data)
+}
+
+// Returns the TIFF data from the EXIF item of a HEIF file
+pub(in crate) fn read_exif_item<R: Read + Seek>(reader: &mut R) -> io::Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(0))?;
+    let meta = loop {
+        let (box_type, size) = read_box_header(reader)?;
+        if &box_type == b"meta" {
+            break read_body(reader, size, MAX_META_BYTES)?;
+        }
+        match size {
+            Some(x) => {
+                let skip = i64::try_from(x).map_err(|_| invalid("Invalid box size"))?;
+                reader.seek(SeekFrom::Current(skip))?;
+            }
+            None => return Err(invalid("No MetaBox in HEIF file")),
+        }
+    };
+
+    // The MetaBox is a full box, so starts with a version and flags
+    let children = child_boxes(meta.get(4..).ok_or_else(|| invalid("Truncated MetaBox"))?)?;
+    let find = |name: &[u8; 4]| children.iter().find(|(x, _)| x == name).map(|(_, x)| *x);
+    let item_id = find_exif_item(find(b"iinf").ok_or_else(|| invalid("No ItemInfoBox"))?)?
+        .ok_or_else(|| invalid("No EXIF item in HEIF file"))?;
+    let location = find_item_location(
+        find(b"iloc").ok_or_else(|| invalid("No ItemLocationBox"))?,
+        item_id,
+    )?
+    .ok_or_else(|| invalid("EXIF item has no location"))?;
+    let mut data = read_extents(reader, &location, find(b"idat"))?;
+
+    // The item starts with the offset of the TIFF header
+    let mut cursor = Cursor::new(&data[..]);
+    let tiff_start = (cursor.read_u32::<BigEndian>()? as usize)
+        .checked_add(4)
+        .filter(|x| *x <= data.len())
+        .ok_or_else(|| invalid("Invalid TIFF header offset in EXIF item"))?;
+    data.drain(..tiff_start);
+    Ok(data)
+}
@@ -0,0 +1,105 @@
+use crate::error::Error;
+use serde::Serialize;
+use std::fmt::Write;
+
+// Width of the longest bar in text output, in characters
+const MAX_BAR_WIDTH: usize = 40;
+// More bins than this means the bin width is much too small for the data
+const MAX_BINS: i64 = 10_000;
+
+#[derive(Serialize)]
+pub(in crate) struct Bin {
+    // Inclusive
+    pub min: f64,
+    // Exclusive
+    pub max: f64,
+    pub count: usize,
+}
+
+// Counts of values in equal width bins, from the bin of the smallest value to that of the largest.
+// Empty bins in between are kept, so that gaps in the distribution are visible
+#[derive(Serialize)]
+pub(in crate) struct Histogram {
+    pub bin_width: f64,
+    pub bins: Vec<Bin>,
+}
+
+// Bin bounds are multiples of the width, which often aren't exact in binary, like
+// 0.30000000000000004
+fn round_bound(value: f64) -> f64 {
+    (value * 1e6).round() / 1e6
+}
+
+impl Histogram {
+    // Values which aren't finite are ignored
+    pub fn new(values: &[f64], bin_width: f64) -> Result<Histogram, Error> {
+        if !bin_width.is_finite() || bin_width <= 0.0 {
+            return Err(Error::InvalidArgument(format!(
+                "Invalid bin width: {}",
+                bin_width
+            )));
+        }
+        let indices: Vec<i64> = values
+            .iter()
+            .filter(|x| x.is_finite())
+            // Nudged up, so that values on a bound, like 0.3 with a width of 0.1, aren't put in the
+            // bin below by rounding error
+            .map(|x| (x / bin_width + 1e-9).floor() as i64)
+            .collect();
+        let mut bins = vec![];
+        if let (Some(&first), Some(&last)) = (indices.iter().min(), indices.iter().max()) {
+            if last - first >= MAX_BINS {
+                return Err(Error::InvalidArgument(format!(
+                    "Bin width of {} is too small for values from {} to {}",
+                    bin_width,
+                    round_bound(first as f64 * bin_width),
+                    round_bound((last + 1) as f64 * bin_width)
+                )));
+            }
+            for i in first..=last {
+                bins.push(Bin {
+                    min: round_bound(i as f64 * bin_width),
+                    max: round_bound((i + 1) as f64 * bin_width),
+                    count: 0,
+                });
+            }
+            for i in indices {
+                bins[(i - first) as usize].count += 1;
+            }
+        }
+        Ok(Histogram { bin_width, bins })
+    }
+
+    // Each bin is labelled with its range, followed by the unit
+    pub fn format_text(&self, unit: &str) -> String {
+        let rows: Vec<(String, usize)> = self
+            .bins
+            .iter()
+            .map(|x| (format!("{} to {}{}", x.min, x.max, unit), x.count))
+            .collect();
+        format_bars(&rows)
+    }
+}
+
+// One line per row, with a bar proportional to its count
+pub(in crate) fn format_bars(rows: &[(String, usize)]) -> String {
+    let label_width = rows.iter().map(|(x, _)| x.len()).max().unwrap_or(0);
+    let max_count = rows.iter().map(|(_, x)| *x).max().unwrap_or(0);
+    let mut result = String::new();
+    for (label, count) in rows.iter() {
+        // Every non-empty row has a visible bar, however small its count
+        let width = match count {
+            0 => 0,
+            _ => (count * MAX_BAR_WIDTH / max_count).max(1),
+        };
+        let line = format!(
+            "  {:<label_width$}  {:>6}  {}",
+            label,
+            count,
+            "#".repeat(width),
+            label_width = label_width
+        );
+        writeln!(result, "{}", line.trim_end()).unwrap();
+    }
+    result
+}
@@ -0,0 +1,115 @@
+use crate::metadata::ImageMetadata;
+use crate::raw::RawFrame;
+use crate::stats::percentile;
+use crate::units::Celsius;
+use serde::Serialize;
+use std::fmt::Write;
+
+// Scales the median absolute deviation to the standard deviation of normally distributed noise
+const MAD_TO_SIGMA: f64 = 1.4826;
+
+#[derive(Serialize)]
+pub(in crate) struct HotPixel {
+    pub row: usize,
+    pub col: usize,
+    pub channel: &'static str,
+    pub value: u16,
+}
+
+// Hot pixel map format, version 1. The JSON form is this struct. The text form is a header of
+// "# key: value" lines for the metadata, followed by one "row col channel value" line per pixel.
+// Rows and columns are in sensor coordinates, including the masked border
+#[derive(Serialize)]
+pub(in crate) struct HotPixelMap {
+    pub version: u32,
+    pub camera_serial_number: Option<String>,
+    pub temperature: Option<Celsius>,
+    pub width: usize,
+    pub height: usize,
+    pub sigma: f64,
+    pub pixels: Vec<HotPixel>,
+}
+
+// Per channel threshold of median + sigma * noise. The noise is estimated from the median absolute
+// deviation, since the hot pixels themselves would inflate the standard deviation
+fn thresholds(frame: &RawFrame, sigma: f64) -> Vec<Option<f64>> {
+    let names = frame.channel_names();
+    let mut channels: Vec<Vec<u16>> = vec![vec![]; names.len()];
+    let (rows, cols) = frame.active_area();
+    frame.for_each_sample(rows, cols, |_, _, channel, value| {
+        channels[channel].push(value)
+    });
+    channels
+        .into_iter()
+        .map(|mut values| {
+            if values.is_empty() {
+                return None;
+            }
+            values.sort_unstable();
+            let median = percentile(&values, 50.0);
+            let mut deviations: Vec<u16> = values
+                .iter()
+                .map(|x| x.max(&median) - x.min(&median))
+                .collect();
+            deviations.sort_unstable();
+            let noise = MAD_TO_SIGMA * f64::from(percentile(&deviations, 50.0));
+            Some(f64::from(median) + sigma * noise)
+        })
+        .collect()
+}
+
+pub(in crate) fn find_hot_pixels(
+    frame: &RawFrame,
+    metadata: Option<&ImageMetadata>,
+    sigma: f64,
+) -> HotPixelMap {
+    let names = frame.channel_names();
+    let thresholds = thresholds(frame, sigma);
+    let mut pixels = vec![];
+    let (rows, cols) = frame.active_area();
+    frame.for_each_sample(rows, cols, |row, col, channel, value| {
+        if let Some(threshold) = thresholds[channel] {
+            if f64::from(value) > threshold {
+                pixels.push(HotPixel {
+                    row,
+                    col,
+                    channel: names[channel],
+                    value,
+                });
+            }
+        }
+    });
+    HotPixelMap {
+        version: 1,
+        camera_serial_number: metadata.map(|x| x.camera_serial_number.clone()),
+        temperature: metadata.and_then(|x| x.temperature),
+        width: frame.image.width,
+        height: frame.image.height,
+        sigma,
+        pixels,
+    }
+}
+
+pub(in crate) fn format_text(map: &HotPixelMap) -> String {
+    let mut output = String::new();
+    writeln!(output, "# darkmagic hot pixel map").unwrap();
+    writeln!(output, "# version: {}", map.version).unwrap();
+    if let Some(serial_number) = &map.camera_serial_number {
+        writeln!(output, "# camera_serial_number: {}", serial_number).unwrap();
+    }
+    if let Some(temperature) = map.temperature {
+        writeln!(output, "# temperature: {}", temperature).unwrap();
+    }
+    writeln!(output, "# width: {}", map.width).unwrap();
+    writeln!(output, "# height: {}", map.height).unwrap();
+    writeln!(output, "# sigma: {}", map.sigma).unwrap();
+    for pixel in map.pixels.iter() {
+        writeln!(
+            output,
+            "{} {} {} {}",
+            pixel.row, pixel.col, pixel.channel, pixel.value
+        )
+        .unwrap();
+    }
+    output
+}
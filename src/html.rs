@@ -0,0 +1,162 @@
+use crate::histogram::Histogram;
+use crate::metadata::ImageMetadata;
+use crate::units::{round_tenths, Seconds, TemperatureUnit};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+// Embedded in the report, so that it's a single file which can be shared
+const STYLE: &str = "body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; margin-bottom: 1.5em; }
+th, td { border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: right; }
+td.path { text-align: left; font-family: monospace; }
+td.missing { background: #f8d7da; }
+.bar { background: #4a7ab5; height: 1em; }";
+
+fn escape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&#39;"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+fn compare_seconds(a: &Seconds, b: &Seconds) -> Ordering {
+    a.partial_cmp(b).unwrap_or(Ordering::Equal)
+}
+
+// Number of frames for each ISO and exposure time. Combinations without any frames are highlighted,
+// since darks for them will have to be shot
+fn write_matrix(html: &mut String, frames: &[&(&str, ImageMetadata)]) {
+    let mut exposure_times: Vec<Seconds> = frames.iter().map(|x| x.1.exposure_time).collect();
+    exposure_times.sort_by(compare_seconds);
+    exposure_times.dedup_by(|a, b| a.shutter_speed() == b.shutter_speed());
+    let mut counts: BTreeMap<u32, BTreeMap<String, usize>> = BTreeMap::new();
+    for (_, metadata) in frames.iter() {
+        *counts
+            .entry(metadata.sensor_sensitivity)
+            .or_default()
+            .entry(metadata.exposure_time.shutter_speed())
+            .or_default() += 1;
+    }
+
+    writeln!(html, "<h3>Frames by ISO and exposure time</h3>\n<table>").unwrap();
+    write!(html, "<tr><th>ISO</th>").unwrap();
+    for exposure_time in exposure_times.iter() {
+        write!(html, "<th>{} s</th>", exposure_time.shutter_speed()).unwrap();
+    }
+    writeln!(html, "</tr>").unwrap();
+    for (sensitivity, row) in counts.iter() {
+        write!(html, "<tr><th>{}</th>", sensitivity).unwrap();
+        for exposure_time in exposure_times.iter() {
+            match row.get(&exposure_time.shutter_speed()) {
+                Some(count) => write!(html, "<td>{}</td>", count).unwrap(),
+                None => write!(html, "<td class=\"missing\">0</td>").unwrap(),
+            }
+        }
+        writeln!(html, "</tr>").unwrap();
+    }
+    writeln!(html, "</table>").unwrap();
+}
+
+fn write_histogram(html: &mut String, frames: &[&(&str, ImageMetadata)], unit: TemperatureUnit) {
+    let temperatures: Vec<f64> = frames
+        .iter()
+        .filter_map(|x| x.1.temperature)
+        .map(|x| f64::from(unit.convert(x)))
+        .collect();
+    writeln!(html, "<h3>Temperatures</h3>").unwrap();
+    let histogram = match Histogram::new(&temperatures, 1.0) {
+        Ok(histogram) => histogram,
+        Err(err) => {
+            writeln!(html, "<p>{}</p>", escape(&err.to_string())).unwrap();
+            return;
+        }
+    };
+    let max_count = histogram.bins.iter().map(|x| x.count).max().unwrap_or(0);
+    writeln!(html, "<table>").unwrap();
+    for bin in histogram.bins.iter() {
+        writeln!(
+            html,
+            "<tr><th>{} to {} {}</th><td>{}</td><td style=\"width: 20em\"><div class=\"bar\" style=\"width: {}%\"></div></td></tr>",
+            bin.min,
+            bin.max,
+            unit.symbol(),
+            bin.count,
+            bin.count * 100 / max_count.max(1)
+        )
+        .unwrap();
+    }
+    writeln!(html, "</table>").unwrap();
+}
+
+fn write_frames(html: &mut String, frames: &[&(&str, ImageMetadata)], unit: TemperatureUnit) {
+    writeln!(
+        html,
+        "<h3>Frames</h3>\n<table>\n<tr><th>File</th><th>Captured</th><th>Serial number</th><th>ISO</th><th>Exposure</th><th>Temperature</th></tr>"
+    )
+    .unwrap();
+    for (path, metadata) in frames.iter() {
+        writeln!(
+            html,
+            "<tr><td class=\"path\">{}</td><td>{}</td><td>{}</td><td>{}</td><td>{} s</td><td>{}</td></tr>",
+            escape(path),
+            escape(metadata.capture_time.as_deref().unwrap_or("unknown")),
+            escape(&metadata.camera_serial_number),
+            metadata.sensor_sensitivity,
+            metadata.exposure_time.shutter_speed(),
+            match metadata.temperature {
+                Some(x) => format!("{} {}", round_tenths(unit.convert(x)), unit.symbol()),
+                None => "unknown".to_string(),
+            }
+        )
+        .unwrap();
+    }
+    writeln!(html, "</table>").unwrap();
+}
+
+// Standalone HTML page with a section for each camera model: which ISO and exposure time
+// combinations have frames, the temperature distribution, and a table of the frames
+pub(in crate) fn html_report(frames: &[(&str, ImageMetadata)], unit: TemperatureUnit) -> String {
+    let mut by_model: BTreeMap<&str, Vec<&(&str, ImageMetadata)>> = BTreeMap::new();
+    for frame in frames.iter() {
+        by_model
+            .entry(frame.1.camera_model.as_str())
+            .or_default()
+            .push(frame);
+    }
+
+    let mut html = String::new();
+    writeln!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Dark library report</title>\n<style>\n{}\n</style>\n</head>\n<body>\n<h1>Dark library report</h1>",
+        STYLE
+    )
+    .unwrap();
+    writeln!(html, "<p>{} frames</p>\n<ul>", frames.len()).unwrap();
+    for (model, frames) in by_model.iter() {
+        writeln!(html, "<li>{}: {} frames</li>", escape(model), frames.len()).unwrap();
+    }
+    writeln!(html, "</ul>").unwrap();
+
+    for (model, frames) in by_model.iter_mut() {
+        // Frames without a capture time go last
+        frames.sort_by(|(_, a), (_, b)| match (&a.capture_time, &b.capture_time) {
+            (Some(a), Some(b)) => a.cmp(b),
+            (a, b) => b.is_some().cmp(&a.is_some()),
+        });
+        writeln!(html, "<h2>{}</h2>", escape(model)).unwrap();
+        write_matrix(&mut html, frames);
+        write_histogram(&mut html, frames, unit);
+        write_frames(&mut html, frames, unit);
+    }
+    writeln!(html, "</body>\n</html>").unwrap();
+    html
+}
@@ -1,162 +1,296 @@
-use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt};
-use exif::{Rational, SRational, Value};
+use crate::ifd_sanity::score_ifd;
+use crate::tiff::{
+    corrupt_ifd, Endian, Ifd, IfdEntry, IfdReader, ParseOptions, SkippedEntry, TIFF_HEADER_BYTES,
+    TIFF_MAGIC,
+};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use exif::Value;
+use log::{debug, warn};
 use std::io;
-use std::io::{Cursor, Error, ErrorKind, Read};
-
-// See: https://www.media.mit.edu/pia/Research/deepview/exif.html#DataForm
-const TYPE_UBYTE: u16 = 1;
-const TYPE_ASCII: u16 = 2;
-const TYPE_USHORT: u16 = 3;
-const TYPE_ULONG: u16 = 4;
-const TYPE_URATIONAL: u16 = 5;
-const TYPE_BYTE: u16 = 6;
-const TYPE_UNDEFINED: u16 = 7;
-const TYPE_SHORT: u16 = 8;
-const TYPE_LONG: u16 = 9;
-const TYPE_RATIONAL: u16 = 10;
-const TYPE_FLOAT: u16 = 11;
-const TYPE_DOUBLE: u16 = 12;
-
-const IFD_BIG_ENDIAN: u16 = 0x4d4d;
-const IFD_LITTLE_ENDIAN: u16 = 0x4949;
-
-pub(in crate) struct IfdEntry {
-    pub tag: u16,
-    pub value: Value,
+
+// Nikon type 3 maker notes start with this, followed by a 2 byte version, 2 bytes of padding, and
+// then an embedded TIFF header
+const NIKON_MAKERNOTE_MAGIC: &[u8] = b"Nikon\0";
+const NIKON_TIFF_HEADER_OFFSET: usize = 10;
+// Fujifilm maker notes start with this, followed by the little endian offset of the IFD
+const FUJIFILM_MAKERNOTE_MAGIC: &[u8] = b"FUJIFILM";
+// Olympus maker notes start with one of these, followed by a byte order marker and a 2 byte
+// version. The IFD immediately follows the header
+const OLYMPUS_MAKERNOTE_MAGIC: &[u8] = b"OLYMPUS\0";
+const OM_SYSTEM_MAKERNOTE_MAGIC: &[u8] = b"OM SYSTEM\0\0\0";
+
+const TAG_OLYMPUS_EQUIPMENT: u16 = 0x2010;
+const TAG_OLYMPUS_CAMERA_SETTINGS: u16 = 0x2020;
+const TAG_OLYMPUS_IMAGE_PROCESSING: u16 = 0x2040;
+const TAG_OLYMPUS_FOCUS_INFO: u16 = 0x2050;
+
+// Panasonic maker notes start with this, and the IFD immediately follows
+const PANASONIC_MAKERNOTE_MAGIC: &[u8] = b"Panasonic\0\0\0";
+
+// Pentax maker notes start with one of these, followed by a byte order marker. Older cameras write
+// two spaces instead of a byte order marker, in which case the container's byte order is used
+const PENTAX_AOC_MAKERNOTE_MAGIC: &[u8] = b"AOC\0";
+const PENTAX_MAKERNOTE_MAGIC: &[u8] = b"PENTAX \0";
+// Block of signed shorts, in the maker note's byte order, holding more temperature readings
+const TAG_PENTAX_TEMP_INFO: u16 = 0x3ff;
+
+// Each sub-IFD is empty if the camera doesn't write it
+pub(in crate) struct OlympusMakerNote {
+    pub main: Vec<IfdEntry>,
+    pub equipment: Vec<IfdEntry>,
+    pub camera_settings: Vec<IfdEntry>,
+    pub image_processing: Vec<IfdEntry>,
+    pub focus_info: Vec<IfdEntry>,
+    // Of all the IFDs
+    pub skipped: Vec<SkippedEntry>,
 }
 
-pub(in crate) fn parse_canon_makernote(data: &[u8]) -> io::Result<Vec<IfdEntry>> {
-    // Read the footer
-    let mut cursor = Cursor::new(data[data.len() - 8..].to_vec());
-    let footer_endian = cursor.read_u16::<BigEndian>()?;
-    if footer_endian == IFD_LITTLE_ENDIAN {
-        parse_canon_helper::<LittleEndian>(data)
-    } else if footer_endian == IFD_BIG_ENDIAN {
-        parse_canon_helper::<BigEndian>(data)
-    } else {
-        Err(Error::from(ErrorKind::InvalidInput))
+pub(in crate) struct PentaxMakerNote {
+    pub main: Ifd,
+    // Empty if the camera doesn't write TempInfo
+    pub temp_info: Vec<i16>,
+}
+
+// Canon maker notes end with a footer laid out like a TIFF header, but with the original offset of
+// the maker note in place of the IFD offset
+pub(in crate) fn parse_canon_makernote(
+    data: &[u8],
+    container_little_endian: bool,
+    options: ParseOptions,
+) -> io::Result<Ifd> {
+    let footer = canon_footer(data)?;
+    match Endian::from_marker(footer) {
+        Some(endian) => parse_canon_helper(data, endian, options),
+        None => {
+            warn!(
+                "Invalid byte order marker in Canon maker note footer: {:#06x}",
+                BigEndian::read_u16(footer)
+            );
+            infer_canon_byte_order(data, container_little_endian, options)
+        }
     }
 }
 
-fn parse_canon_helper<E: ByteOrder>(data: &[u8]) -> io::Result<Vec<IfdEntry>> {
-    // Read the footer
-    let mut cursor = Cursor::new(data[data.len() - 8..].to_vec());
-    // ignored
-    let _footer_endian = cursor.read_u16::<E>()?;
-    let fourty_two = cursor.read_u16::<E>()?;
-    assert_eq!(fourty_two, 42);
-    // The original offset of the maker note. All pointers are relative to this address, so we must
-    // pad the buffer with this many bytes
-    let original_offset = cursor.read_u32::<E>()? as isize;
-
-    parse_ifd::<E>(data, -original_offset)
+// Try both byte orders, starting with the one used by the enclosing EXIF container, and keep
+// whichever produces the more plausible IFD
+fn infer_canon_byte_order(
+    data: &[u8],
+    container_little_endian: bool,
+    options: ParseOptions,
+) -> io::Result<Ifd> {
+    let little_score = score_ifd::<LittleEndian>(data, 0).value();
+    let big_score = score_ifd::<BigEndian>(data, 0).value();
+    debug!(
+        "Canon maker note byte order scores: little endian = {}, big endian = {}",
+        little_score, big_score
+    );
+    if little_score == 0.0 && big_score == 0.0 {
+        return Err(corrupt_ifd(
+            0,
+            "Canon maker note is not an IFD in either byte order",
+        ));
+    }
+
+    let little_endian = if little_score == big_score {
+        container_little_endian
+    } else {
+        little_score > big_score
+    };
+    parse_canon_helper(data, Endian::new(little_endian), options)
 }
 
-fn parse_ifd<E: ByteOrder>(data: &[u8], pointer_fixup: isize) -> io::Result<Vec<IfdEntry>> {
-    let mut cursor = Cursor::new(data.to_vec());
-    let entry_count = cursor.read_u16::<E>()?;
-
-    let mut entries = vec![];
-    for _ in 0..entry_count {
-        let tag = cursor.read_u16::<E>()?;
-        let value_type = cursor.read_u16::<E>()?;
-        let element_width = type_width(value_type)?;
-        let element_count = cursor.read_u32::<E>()?;
-        let data_bytes = element_width
-            .checked_mul(element_count as usize)
-            .ok_or_else(|| Error::from(ErrorKind::InvalidInput))?;
-        let value = if data_bytes <= 4 {
-            // value(s) is inline
-            let mut temp = [0u8; 4];
-            cursor.read_exact(&mut temp)?;
-            parse_value::<E>(value_type, &temp[..data_bytes])?
-        } else {
-            let data_ptr = (cursor.read_u32::<E>()? as isize) + pointer_fixup;
-            if data_ptr < 0 || data_ptr + data_bytes as isize >= data.len() as isize {
-                return Err(Error::from(ErrorKind::InvalidInput));
-            }
-            let data_ptr = data_ptr as usize;
-            parse_value::<E>(value_type, &data[data_ptr..(data_ptr + data_bytes)])?
-        };
-        entries.push(IfdEntry { tag, value });
+fn canon_footer(data: &[u8]) -> io::Result<&[u8]> {
+    data.len()
+        .checked_sub(TIFF_HEADER_BYTES)
+        .map(|start| &data[start..])
+        .ok_or_else(|| corrupt_ifd(0, "Canon maker note is too short for its footer"))
+}
+
+fn parse_canon_helper(data: &[u8], endian: Endian, options: ParseOptions) -> io::Result<Ifd> {
+    let footer = canon_footer(data)?;
+    if endian.read_u16(&footer[2..]) != TIFF_MAGIC {
+        return Err(corrupt_ifd(
+            data.len() - TIFF_HEADER_BYTES + 2,
+            "invalid magic number in Canon maker note footer",
+        ));
     }
+    // The original offset of the maker note. All pointers are relative to this address
+    let original_offset = endian.read_u32(&footer[4..]) as isize;
 
-    Ok(entries)
+    IfdReader::new(data, endian, options)
+        .with_pointer_fixup(-original_offset)
+        .read_ifd(0)
 }
 
-fn parse_value<E: ByteOrder>(data_type: u16, data: &[u8]) -> io::Result<Value> {
-    Ok(match data_type {
-        TYPE_BYTE => Value::SByte(data.iter().map(|x| *x as i8).collect()),
-        TYPE_UBYTE => Value::Byte(data.to_vec()),
-        TYPE_ASCII => Value::Ascii(data.split(|x| *x == 0).map(|x| x.to_vec()).collect()),
-        // TODO: is it safe to pass zero here?
-        TYPE_UNDEFINED => Value::Undefined(data.to_vec(), 0),
-        TYPE_SHORT => {
-            let mut value = vec![0i16; data.len() / type_width(data_type)?];
-            E::read_i16_into(data, &mut value);
-            Value::SShort(value)
-        }
-        TYPE_USHORT => {
-            let mut value = vec![0u16; data.len() / type_width(data_type)?];
-            E::read_u16_into(data, &mut value);
-            Value::Short(value)
-        }
-        TYPE_LONG => {
-            let mut value = vec![0i32; data.len() / type_width(data_type)?];
-            E::read_i32_into(data, &mut value);
-            Value::SLong(value)
-        }
-        TYPE_ULONG => {
-            let mut value = vec![0u32; data.len() / type_width(data_type)?];
-            E::read_u32_into(data, &mut value);
-            Value::Long(value)
-        }
-        TYPE_FLOAT => {
-            let mut value = vec![0f32; data.len() / type_width(data_type)?];
-            E::read_f32_into(data, &mut value);
-            Value::Float(value)
-        }
-        TYPE_RATIONAL => {
-            let mut value = vec![0i32; 2 * data.len() / type_width(data_type)?];
-            E::read_i32_into(data, &mut value);
-            let (numerators, denominators): (Vec<i32>, Vec<i32>) =
-                value.iter().partition(|x| **x % 2 == 0);
-            Value::SRational(
-                numerators
-                    .iter()
-                    .zip(denominators.iter())
-                    .map(|(x, y)| SRational::from((*x, *y)))
-                    .collect(),
-            )
-        }
-        TYPE_URATIONAL => {
-            let mut value = vec![0u32; 2 * data.len() / type_width(data_type)?];
-            E::read_u32_into(data, &mut value);
-            let (numerators, denominators): (Vec<u32>, Vec<u32>) =
-                value.iter().partition(|x| **x % 2 == 0);
-            Value::Rational(
-                numerators
-                    .iter()
-                    .zip(denominators.iter())
-                    .map(|(x, y)| Rational::from((*x, *y)))
-                    .collect(),
-            )
+// Nikon maker notes contain a complete TIFF structure, so unlike Canon all pointers are relative to
+// the embedded TIFF header rather than to the start of the EXIF data. Entries of any IFDs chained
+// after the first are read too, but tags are looked up by their first occurrence, so the first IFD
+// takes precedence
+pub(in crate) fn parse_nikon_makernote(data: &[u8], options: ParseOptions) -> io::Result<Ifd> {
+    if !data.starts_with(NIKON_MAKERNOTE_MAGIC) {
+        return Err(corrupt_ifd(0, "missing Nikon maker note header"));
+    }
+    let tiff = data
+        .get(NIKON_TIFF_HEADER_OFFSET..)
+        .ok_or_else(|| corrupt_ifd(0, "Nikon maker note header is truncated"))?;
+    let (reader, ifd_offset) = IfdReader::from_tiff_header(tiff, options)?;
+
+    let mut ifds = reader.chain(ifd_offset);
+    let mut result = Ifd {
+        entries: vec![],
+        skipped: vec![],
+        next: None,
+    };
+    if let Some(ifd) = ifds.next() {
+        let ifd = ifd?;
+        result.entries = ifd.entries;
+        result.skipped = ifd.skipped;
+    }
+    // Only the first IFD is required, so a damaged chain after it just ends the chain
+    for ifd in ifds {
+        match ifd {
+            Ok(ifd) => {
+                result.entries.extend(ifd.entries);
+                result.skipped.extend(ifd.skipped);
+            }
+            Err(err) => warn!("Ignoring IFD chained after the Nikon maker note: {}", err),
         }
-        TYPE_DOUBLE => {
-            let mut value = vec![0f64; data.len() / type_width(data_type)?];
-            E::read_f64_into(data, &mut value);
-            Value::Double(value)
+    }
+    Ok(result)
+}
+
+// Fujifilm maker notes are always little endian, and pointers are relative to the start of the
+// maker note
+pub(in crate) fn parse_fujifilm_makernote(data: &[u8], options: ParseOptions) -> io::Result<Ifd> {
+    if !data.starts_with(FUJIFILM_MAKERNOTE_MAGIC) {
+        return Err(corrupt_ifd(0, "missing Fujifilm maker note header"));
+    }
+    let ifd_offset = data
+        .get(FUJIFILM_MAKERNOTE_MAGIC.len()..FUJIFILM_MAKERNOTE_MAGIC.len() + 4)
+        .map(LittleEndian::read_u32)
+        .ok_or_else(|| corrupt_ifd(0, "Fujifilm maker note header is truncated"))?
+        as usize;
+
+    IfdReader::new(data, Endian::Little, options).read_ifd(ifd_offset)
+}
+
+// Older Olympus cameras use a different header, with pointers relative to the enclosing EXIF data,
+// which isn't supported. Pointers, including those to the sub-IFDs, are relative to the start of
+// the maker note
+pub(in crate) fn parse_olympus_makernote(
+    data: &[u8],
+    options: ParseOptions,
+) -> io::Result<OlympusMakerNote> {
+    let header_length = if data.starts_with(OLYMPUS_MAKERNOTE_MAGIC) {
+        OLYMPUS_MAKERNOTE_MAGIC.len() + 4
+    } else if data.starts_with(OM_SYSTEM_MAKERNOTE_MAGIC) {
+        OM_SYSTEM_MAKERNOTE_MAGIC.len() + 4
+    } else {
+        return Err(corrupt_ifd(0, "missing Olympus maker note header"));
+    };
+    if data.len() < header_length {
+        return Err(corrupt_ifd(0, "Olympus maker note header is truncated"));
+    }
+    let endian = Endian::from_marker(&data[(header_length - 4)..]).ok_or_else(|| {
+        corrupt_ifd(
+            header_length - 4,
+            "invalid byte order marker in Olympus maker note",
+        )
+    })?;
+    let reader = IfdReader::new(data, endian, options);
+    let mut main = reader.read_ifd(header_length)?;
+    let mut skipped = std::mem::take(&mut main.skipped);
+    let mut read_sub_ifd = |tag: u16| -> io::Result<Vec<IfdEntry>> {
+        match main.find(tag) {
+            Some(Value::Long(offset)) if offset.len() == 1 => {
+                let ifd = reader.read_ifd(offset[0] as usize)?;
+                skipped.extend(ifd.skipped);
+                Ok(ifd.entries)
+            }
+            _ => Ok(vec![]),
         }
-        _ => return Err(Error::from(ErrorKind::InvalidInput)),
+    };
+    let equipment = read_sub_ifd(TAG_OLYMPUS_EQUIPMENT)?;
+    let camera_settings = read_sub_ifd(TAG_OLYMPUS_CAMERA_SETTINGS)?;
+    let image_processing = read_sub_ifd(TAG_OLYMPUS_IMAGE_PROCESSING)?;
+    let focus_info = read_sub_ifd(TAG_OLYMPUS_FOCUS_INFO)?;
+
+    Ok(OlympusMakerNote {
+        main: main.entries,
+        equipment,
+        camera_settings,
+        image_processing,
+        focus_info,
+        skipped,
     })
 }
 
-fn type_width(data_type: u16) -> io::Result<usize> {
-    Ok(match data_type {
-        TYPE_BYTE | TYPE_UBYTE | TYPE_ASCII | TYPE_UNDEFINED => 1,
-        TYPE_SHORT | TYPE_USHORT => 2,
-        TYPE_LONG | TYPE_ULONG | TYPE_FLOAT => 4,
-        TYPE_RATIONAL | TYPE_URATIONAL | TYPE_DOUBLE => 8,
-        _ => return Err(Error::from(ErrorKind::InvalidData)),
-    })
+// Finds the offset of the maker note within the enclosing TIFF data. The offset recorded by the
+// EXIF reader is checked first, and the maker note is only searched for if it's wrong, as it is for
+// maker notes which were moved out of their original file
+fn find_makernote_offset(
+    makernote: &[u8],
+    recorded_offset: usize,
+    tiff: &[u8],
+) -> io::Result<usize> {
+    let recorded = recorded_offset
+        .checked_add(makernote.len())
+        .and_then(|end| tiff.get(recorded_offset..end));
+    if recorded == Some(makernote) {
+        return Ok(recorded_offset);
+    }
+    tiff.windows(makernote.len())
+        .position(|x| x == makernote)
+        .ok_or_else(|| corrupt_ifd(recorded_offset, "maker note not found in the TIFF data"))
+}
+
+// Panasonic pointers are relative to the enclosing TIFF header, so the maker note has to be parsed
+// in place
+pub(in crate) fn parse_panasonic_makernote(
+    makernote: &[u8],
+    makernote_offset: usize,
+    tiff: &[u8],
+    little_endian: bool,
+    options: ParseOptions,
+) -> io::Result<Ifd> {
+    if !makernote.starts_with(PANASONIC_MAKERNOTE_MAGIC) {
+        return Err(corrupt_ifd(0, "missing Panasonic maker note header"));
+    }
+    let ifd_offset =
+        find_makernote_offset(makernote, makernote_offset, tiff)? + PANASONIC_MAKERNOTE_MAGIC.len();
+    IfdReader::new(tiff, Endian::new(little_endian), options).read_ifd(ifd_offset)
+}
+
+// Pointers in maker notes with the AOC header are relative to the enclosing TIFF header, like
+// Panasonic, whereas those with the PENTAX header are relative to the start of the maker note
+pub(in crate) fn parse_pentax_makernote(
+    makernote: &[u8],
+    makernote_offset: usize,
+    tiff: &[u8],
+    container_little_endian: bool,
+    options: ParseOptions,
+) -> io::Result<PentaxMakerNote> {
+    let (magic, data, base) = if makernote.starts_with(PENTAX_AOC_MAKERNOTE_MAGIC) {
+        let base = find_makernote_offset(makernote, makernote_offset, tiff)?;
+        (PENTAX_AOC_MAKERNOTE_MAGIC, tiff, base)
+    } else if makernote.starts_with(PENTAX_MAKERNOTE_MAGIC) {
+        (PENTAX_MAKERNOTE_MAGIC, makernote, 0)
+    } else {
+        return Err(corrupt_ifd(0, "missing Pentax maker note header"));
+    };
+    if makernote.len() < magic.len() + 2 {
+        return Err(corrupt_ifd(0, "Pentax maker note header is truncated"));
+    }
+    let endian = Endian::from_marker(&makernote[magic.len()..])
+        .unwrap_or_else(|| Endian::new(container_little_endian));
+    let ifd_offset = base + magic.len() + 2;
+    let main = IfdReader::new(data, endian, options).read_ifd(ifd_offset)?;
+    let temp_info = match main.find(TAG_PENTAX_TEMP_INFO) {
+        Some(Value::Undefined(data, _)) => data
+            .chunks_exact(2)
+            .map(|x| endian.read_u16(x) as i16)
+            .collect(),
+        _ => vec![],
+    };
+    Ok(PentaxMakerNote { main, temp_info })
 }
@@ -0,0 +1,159 @@
+use crate::tiff::{type_width, IFD_ENTRY_BYTES};
+use byteorder::ByteOrder;
+
+// Real IFDs rarely have more than a few hundred entries, so anything larger is almost certainly
+// garbage being interpreted as an entry count
+const MAX_PLAUSIBLE_ENTRIES: u16 = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(in crate) struct IfdScore {
+    pub entry_count: u16,
+    // Fraction of entries with a data type defined by the TIFF/EXIF specs
+    pub known_type_ratio: f32,
+    // Fraction of adjacent entry pairs whose tags are in ascending order, as the spec requires
+    pub monotonic_tag_ratio: f32,
+}
+
+impl IfdScore {
+    // Combined plausibility in the range [0, 1]. 0 means the data is definitely not an IFD
+    pub fn value(&self) -> f32 {
+        if self.entry_count == 0 {
+            return 0.0;
+        }
+        self.known_type_ratio * self.monotonic_tag_ratio
+    }
+}
+
+// Scores how much the data at the given offset looks like an IFD in byte order E, without fully
+// parsing it. Used to choose between candidate byte orders and offsets for damaged files
+pub(in crate) fn score_ifd<E: ByteOrder>(data: &[u8], offset: usize) -> IfdScore {
+    let zero = IfdScore {
+        entry_count: 0,
+        known_type_ratio: 0.0,
+        monotonic_tag_ratio: 0.0,
+    };
+    if offset >= data.len() || data.len() - offset < 2 {
+        return zero;
+    }
+    let entry_count = E::read_u16(&data[offset..]);
+    if entry_count == 0 || entry_count > MAX_PLAUSIBLE_ENTRIES {
+        return zero;
+    }
+    let entries_start = offset + 2;
+    if entries_start + entry_count as usize * IFD_ENTRY_BYTES > data.len() {
+        return zero;
+    }
+
+    let mut known_types = 0;
+    let mut monotonic_tags = 0;
+    let mut previous_tag = None;
+    for i in 0..entry_count as usize {
+        let entry = &data[(entries_start + i * IFD_ENTRY_BYTES)..];
+        let tag = E::read_u16(entry);
+        let value_type = E::read_u16(&entry[2..]);
+        if type_width(value_type).is_ok() {
+            known_types += 1;
+        }
+        if let Some(previous) = previous_tag {
+            if tag > previous {
+                monotonic_tags += 1;
+            }
+        }
+        previous_tag = Some(tag);
+    }
+
+    IfdScore {
+        entry_count,
+        known_type_ratio: known_types as f32 / entry_count as f32,
+        monotonic_tag_ratio: if entry_count == 1 {
+            1.0
+        } else {
+            monotonic_tags as f32 / (entry_count - 1) as f32
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::{BigEndian, LittleEndian};
+
+    // IFD of (tag, type) entries in byte order E, with zeroed counts and values
+    fn ifd<E: ByteOrder>(entries: &[(u16, u16)]) -> Vec<u8> {
+        let mut data = vec![0; 2 + entries.len() * IFD_ENTRY_BYTES];
+        E::write_u16(&mut data, entries.len() as u16);
+        for (i, (tag, value_type)) in entries.iter().enumerate() {
+            let entry = &mut data[(2 + i * IFD_ENTRY_BYTES)..];
+            E::write_u16(entry, *tag);
+            E::write_u16(&mut entry[2..], *value_type);
+        }
+        data
+    }
+
+    const VALID: &[(u16, u16)] = &[(0x1, 3), (0x2, 4), (0x3, 2), (0x10, 7)];
+
+    #[test]
+    fn valid_ifd_scores_highest() {
+        let data = ifd::<LittleEndian>(VALID);
+        let score = score_ifd::<LittleEndian>(&data, 0);
+        assert_eq!(score.entry_count, 4);
+        assert_eq!(score.value(), 1.0);
+    }
+
+    #[test]
+    fn wrong_byte_order_scores_lower() {
+        let data = ifd::<BigEndian>(VALID);
+        let right = score_ifd::<BigEndian>(&data, 0).value();
+        let wrong = score_ifd::<LittleEndian>(&data, 0).value();
+        assert!(right > wrong, "{} <= {}", right, wrong);
+    }
+
+    #[test]
+    fn huge_entry_count_scores_zero() {
+        let mut data = ifd::<LittleEndian>(VALID);
+        LittleEndian::write_u16(&mut data, MAX_PLAUSIBLE_ENTRIES + 1);
+        assert_eq!(score_ifd::<LittleEndian>(&data, 0).value(), 0.0);
+    }
+
+    #[test]
+    fn invalid_types_score_lower() {
+        let valid = score_ifd::<LittleEndian>(&ifd::<LittleEndian>(VALID), 0);
+        let some_invalid = score_ifd::<LittleEndian>(
+            &ifd::<LittleEndian>(&[(0x1, 3), (0x2, 99), (0x3, 2), (0x10, 0)]),
+            0,
+        );
+        let all_invalid =
+            score_ifd::<LittleEndian>(&ifd::<LittleEndian>(&[(0x1, 99), (0x2, 0)]), 0);
+        assert_eq!(some_invalid.known_type_ratio, 0.5);
+        assert!(valid.value() > some_invalid.value());
+        assert!(some_invalid.value() > all_invalid.value());
+        assert_eq!(all_invalid.value(), 0.0);
+    }
+
+    #[test]
+    fn unordered_tags_score_lower() {
+        let valid = score_ifd::<LittleEndian>(&ifd::<LittleEndian>(VALID), 0).value();
+        let unordered = score_ifd::<LittleEndian>(
+            &ifd::<LittleEndian>(&[(0x10, 3), (0x2, 4), (0x3, 2), (0x1, 7)]),
+            0,
+        )
+        .value();
+        assert!(valid > unordered, "{} <= {}", valid, unordered);
+    }
+
+    #[test]
+    fn out_of_range_offsets_score_zero() {
+        let data = ifd::<LittleEndian>(VALID);
+        assert_eq!(score_ifd::<LittleEndian>(&data, data.len()).value(), 0.0);
+        assert_eq!(
+            score_ifd::<LittleEndian>(&data, data.len() - 1).value(),
+            0.0
+        );
+        assert_eq!(score_ifd::<LittleEndian>(&data, usize::MAX).value(), 0.0);
+        // The entries run past the end of the data
+        assert_eq!(
+            score_ifd::<LittleEndian>(&data[..data.len() - 1], 0).value(),
+            0.0
+        );
+    }
+}
@@ -0,0 +1,64 @@
+use crate::heif::{invalid, read_body, read_box_header};
+use std::convert::TryFrom;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+// JPEG XL files which use the box based container start with this. Bare codestreams can't contain
+// EXIF data
+const JXL_SIGNATURE: &[u8] = b"\0\0\0\x0cJXL \r\n\x87\n";
+const MAX_EXIF_BYTES: u64 = 16 * 1024 * 1024;
+
+// Checks for the container signature, and rewinds the reader to the start of the file
+pub(in crate) fn is_jxl<R: Read + Seek>(reader: &mut R) -> io::Result<bool> {
+    let mut signature = [0u8; 12];
+    let result = reader.read_exact(&mut signature);
+    reader.seek(SeekFrom::Start(0))?;
+    match result {
+        Ok(()) => Ok(signature == JXL_SIGNATURE),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+// Returns the TIFF data from the Exif box
+pub(in crate) fn read_exif_box<R: Read + Seek>(reader: &mut R) -> io::Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(0))?;
+    loop {
+        let (box_type, size) = read_box_header(reader)?;
+        match &box_type {
+            b"Exif" => {
+                let mut data = read_body(reader, size, MAX_EXIF_BYTES)?;
+                // The box starts with the offset of the TIFF header
+                if data.len() < 4 {
+                    return Err(invalid("Truncated Exif box"));
+                }
+                let tiff_start = (u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+                    as usize)
+                    .checked_add(4)
+                    .filter(|x| *x <= data.len())
+                    .ok_or_else(|| invalid("Invalid TIFF header offset in Exif box"))?;
+                data.drain(..tiff_start);
+                return Ok(data);
+            }
+            b"brob" => {
+                let mut compressed_type = [0u8; 4];
+                reader.read_exact(&mut compressed_type)?;
+                if &compressed_type == b"Exif" {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Brotli compressed EXIF data is not supported",
+                    ));
+                }
+                reader.seek(SeekFrom::Current(-4))?;
+            }
+            _ => {}
+        }
+        match size {
+            Some(x) => {
+                let skip = i64::try_from(x).map_err(|_| invalid("Invalid box size"))?;
+                reader.seek(SeekFrom::Current(skip))?;
+            }
+            None => return Err(invalid("No Exif box in JPEG XL file")),
+        }
+    }
+}
@@ -1,29 +1,772 @@
+mod apex;
+mod audit;
+mod cache;
+mod canon_video;
+mod compare;
+mod duplicates;
 mod error;
+mod exiftool;
+mod filter;
+mod fits;
+mod heif;
+mod histogram;
+#[cfg(feature = "gpl")]
+mod hot_pixels;
+mod html;
 mod ifd;
+mod ifd_sanity;
+mod jxl;
 mod metadata;
+mod pretty;
+mod preview;
+mod progress;
+mod raf;
+#[cfg(feature = "gpl")]
+mod raw;
+#[cfg(feature = "remote")]
+mod remote;
+mod rename;
+mod report;
+#[cfg(feature = "server")]
+mod server;
+#[cfg(feature = "gpl")]
+mod stack;
+#[cfg(feature = "gpl")]
+mod stats;
+mod summary;
+mod tiff;
+mod units;
+mod vendor_tiff;
+#[cfg(feature = "watch")]
+mod watch;
+mod xmp;
 
+use crate::cache::MetadataCache;
 use crate::error::Error;
+use crate::filter::Filter;
+use crate::metadata::ImageMetadata;
 use crate::metadata::MetadataParser;
-use clap::{crate_version, App, Arg};
+use crate::progress::{Progress, ProgressFormat};
+use crate::summary::Summary;
+use crate::tiff::{ParseMode, ParseOptions};
+use crate::units::{Celsius, TemperatureUnit};
+use atty::Stream;
+use clap::{crate_version, App, AppSettings, Arg, ArgMatches, SubCommand};
 use log::LevelFilter;
+use serde_json::json;
+use std::io::Read;
+use std::path::Path;
 
-fn main() -> Result<(), Error> {
-    let matches = App::new("DarkMagic")
+// Input path which means the image should be read from stdin
+const STDIN_PATH: &str = "-";
+
+// Returns the metadata region of the file, if the path is a URL
+#[cfg(feature = "remote")]
+fn read_remote(parser: &MetadataParser, path: &str) -> Result<Option<Vec<u8>>, Error> {
+    if remote::is_url(path) {
+        Ok(Some(remote::fetch_metadata(parser, path)?))
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(not(feature = "remote"))]
+fn read_remote(_parser: &MetadataParser, _path: &str) -> Result<Option<Vec<u8>>, Error> {
+    Ok(None)
+}
+
+// Reads the metadata of a single input, and writes any requested sidecars. Returns None if it
+// doesn't match the filter
+fn process_file(
+    parser: &MetadataParser,
+    path: &str,
+    matches: &ArgMatches,
+    filter: Option<&Filter>,
+) -> Result<Option<ImageMetadata>, Error> {
+    let input_data = if path == STDIN_PATH {
+        let mut data = vec![];
+        std::io::stdin().read_to_end(&mut data)?;
+        Some(data)
+    } else {
+        read_remote(parser, path)?
+    };
+    if input_data.is_some()
+        && (matches.is_present("fits-sidecar") || matches.is_present("write-xmp"))
+    {
+        return Err(Error::Unsupported(
+            "Sidecar files can only be written for local files".to_string(),
+        ));
+    }
+
+    let metadata = match &input_data {
+        Some(data) => parser.read_bytes(data)?,
+        None => parser.read_file(path)?,
+    };
+    if matches!(filter, Some(x) if !x.matches(&metadata)) {
+        return Ok(None);
+    }
+
+    if matches.is_present("audit") {
+        let mismatches = match &input_data {
+            Some(data) => parser.audit_bytes(data)?,
+            None => parser.audit_file(path)?,
+        };
+        for mismatch in mismatches {
+            eprintln!(
+                "{}: {} of {} differs from {} of {} by {:.1} stops",
+                path,
+                mismatch.source,
+                mismatch.value,
+                mismatch.reference,
+                mismatch.reference_value,
+                mismatch.stops
+            );
+        }
+    }
+
+    if matches.is_present("fits-sidecar") {
+        std::fs::write(
+            Path::new(path).with_extension("hdr"),
+            fits::format_header(&metadata),
+        )?;
+    }
+
+    if matches.is_present("write-xmp") {
+        xmp::write_sidecar(Path::new(path), &metadata)?;
+    }
+
+    Ok(Some(metadata))
+}
+
+// Reads the metadata of all the files which match the filter, reporting any which can't be read on
+// stderr. Also returns the exit status for the first of those failures
+fn read_files<'a>(
+    parser: &MetadataParser,
+    paths: &[&'a str],
+    filter: Option<&Filter>,
+    progress: &mut Progress,
+) -> (Vec<(&'a str, ImageMetadata)>, Option<i32>) {
+    let mut files = vec![];
+    let mut failure = None;
+    for path in paths.iter().copied() {
+        match parser.read_file(path) {
+            Ok(metadata) => {
+                progress.file_done(path, None);
+                if !matches!(filter, Some(x) if !x.matches(&metadata)) {
+                    files.push((path, metadata));
+                }
+            }
+            Err(err) => {
+                progress.file_done(path, Some(&err));
+                eprintln!("{}: {}", path, err);
+                failure.get_or_insert(err.exit_code());
+            }
+        }
+    }
+    progress.finish();
+    (files, failure)
+}
+
+// Batch commands show a progress bar when run interactively, unless it's turned off with --quiet
+fn progress_format(matches: &ArgMatches, bar_by_default: bool) -> Option<ProgressFormat> {
+    match matches.value_of("progress") {
+        Some("json") => Some(ProgressFormat::Json),
+        Some(_) => Some(ProgressFormat::Bar),
+        None if bar_by_default
+            && !matches.is_present("quiet")
+            && atty::is(Stream::Stdout)
+            && atty::is(Stream::Stderr) =>
+        {
+            Some(ProgressFormat::Bar)
+        }
+        None => None,
+    }
+}
+
+fn parse_temperature(value: Option<&str>) -> Result<Option<Celsius>, Error> {
+    value
+        .map(|x| {
+            x.parse::<f32>()
+                .map(Celsius)
+                .map_err(|_| Error::InvalidArgument(format!("Invalid temperature: {}", x)))
+        })
+        .transpose()
+}
+
+fn filter_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("filter")
+        .long("filter")
+        .takes_value(true)
+        .value_name("EXPRESSION")
+        .help("Only uses frames which match the expression, like \"temp>=18 && temp<=22 && iso==1600\". Fields: temp iso exposure serial model datetime lenr")
+}
+
+fn metadata_parser(matches: &ArgMatches) -> Result<MetadataParser, Error> {
+    let mut options = ParseOptions::default();
+    if matches.is_present("permissive") {
+        options.mode = ParseMode::Permissive;
+    }
+    if let Some(x) = matches.value_of("max-value-size") {
+        options.max_value_bytes = x
+            .parse::<usize>()
+            .map_err(|_| Error::InvalidArgument(format!("Invalid maximum value size: {}", x)))?;
+    }
+    let max_scan_bytes = matches
+        .value_of("max-scan-bytes")
+        .map(|x| {
+            x.parse::<u64>()
+                .map_err(|_| Error::InvalidArgument(format!("Invalid maximum scan size: {}", x)))
+        })
+        .transpose()?;
+    let cache = if matches.is_present("cache") {
+        Some(MetadataCache::new(options)?)
+    } else {
+        None
+    };
+    Ok(MetadataParser::new(options)
+        .with_max_scan_bytes(max_scan_bytes)
+        .with_cache(cache))
+}
+
+fn parse_temperature_unit(matches: &ArgMatches) -> TemperatureUnit {
+    TemperatureUnit::parse(matches.value_of("temperature-unit").unwrap()).unwrap()
+}
+
+fn parse_filter(matches: &ArgMatches) -> Result<Option<Filter>, Error> {
+    matches
+        .value_of("filter")
+        .map(|x| {
+            Filter::parse(
+                x,
+                parse_temperature_unit(matches),
+                matches.is_present("allow-estimated"),
+            )
+        })
+        .transpose()
+}
+
+// A difference between two temperatures, in the unit set by --temperature-unit. Unlike a
+// temperature it has no offset, so it isn't converted to Celsius
+fn parse_temperature_delta(value: Option<&str>) -> Result<Option<f32>, Error> {
+    match parse_temperature(value)? {
+        Some(Celsius(x)) if x >= 0.0 => Ok(Some(x)),
+        Some(_) => Err(Error::InvalidArgument(format!(
+            "Invalid temperature delta: {}",
+            value.unwrap_or_default()
+        ))),
+        None => Ok(None),
+    }
+}
+
+fn parse_temperature_bin(value: Option<&str>) -> Result<f32, Error> {
+    match parse_temperature(value)? {
+        Some(Celsius(x)) if x > 0.0 => Ok(x),
+        _ => Err(Error::InvalidArgument(format!(
+            "Invalid temperature bin: {}",
+            value.unwrap_or_default()
+        ))),
+    }
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("Error: {}", err);
+        std::process::exit(err.exit_code());
+    }
+}
+
+fn run() -> Result<(), Error> {
+    let report = SubCommand::with_name("report")
+        .about("Summarizes the metadata of a set of frames")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("temperature")
+                .about("Prints the sensor temperature of each frame over time")
+                .arg(filter_arg())
+                .arg(
+                    Arg::with_name("band-min")
+                        .long("band-min")
+                        .takes_value(true)
+                        .value_name("DEGREES")
+                        .help("Flags frames colder than this"),
+                )
+                .arg(
+                    Arg::with_name("band-max")
+                        .long("band-max")
+                        .takes_value(true)
+                        .value_name("DEGREES")
+                        .help("Flags frames warmer than this"),
+                )
+                .arg(
+                    Arg::with_name("INPUT_FILE")
+                        .help("Sets the input files to use")
+                        .required(true)
+                        .multiple(true)
+                        .index(1),
+                ),
+        );
+    let report = report.subcommand(
+        SubCommand::with_name("html")
+            .about("Writes a standalone HTML report of the frames, with a section for each camera model")
+            .arg(filter_arg())
+            .arg(
+                Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .required(true)
+                    .help("Sets the HTML file to write"),
+            )
+            .arg(
+                Arg::with_name("INPUT_FILE")
+                    .help("Sets the input files to use")
+                    .required(true)
+                    .multiple(true)
+                    .index(1),
+            ),
+    );
+    let report = report.subcommand(
+        SubCommand::with_name("histogram")
+            .about("Prints a histogram of a numeric field over the frames")
+            .arg(filter_arg())
+            .arg(
+                Arg::with_name("field")
+                    .long("field")
+                    .takes_value(true)
+                    .possible_values(&["temperature", "iso", "exposure", "shutter-count"])
+                    .default_value("temperature")
+                    .help("Sets the field to bin. Temperatures are binned in --temperature-unit, and exposures in seconds"),
+            )
+            .arg(
+                Arg::with_name("bin")
+                    .long("bin")
+                    .takes_value(true)
+                    .value_name("WIDTH")
+                    .default_value("1")
+                    .help("Sets the width of each bin"),
+            )
+            .arg(
+                Arg::with_name("format")
+                    .long("format")
+                    .takes_value(true)
+                    .possible_values(&["json", "text"])
+                    .default_value("text")
+                    .help("Sets the format of the histogram"),
+            )
+            .arg(
+                Arg::with_name("INPUT_FILE")
+                    .help("Sets the input files to use")
+                    .required(true)
+                    .multiple(true)
+                    .index(1),
+            ),
+    );
+    #[cfg(feature = "gpl")]
+    let report = report.subcommand(
+        SubCommand::with_name("dark-current")
+            .about("Fits the dark current of each ISO from darks of different exposure times")
+            .arg(filter_arg())
+            .arg(
+                Arg::with_name("INPUT_FILE")
+                    .help("Sets the input files to use")
+                    .required(true)
+                    .multiple(true)
+                    .index(1),
+            ),
+    );
+    let app = App::new("DarkMagic")
         .version(crate_version!())
+        .after_help(
+            "EXIT STATUS:\n    0    Success\n    1    Other error, such as an invalid argument\n    2    Unsupported camera or file format\n    3    Corrupt or incomplete metadata\n    4    I/O error\n    5    Metadata not found within --max-scan-bytes\n\nWhen several files are read, the status is that of the first file which failed",
+        )
         .author("Christopher Berner")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("v")
                 .short("v")
                 .multiple(true)
                 .help("Sets the level of verbosity"),
         )
+        .arg(
+            Arg::with_name("progress")
+                .long("progress")
+                .takes_value(true)
+                .possible_values(&["json", "bar"])
+                .global(true)
+                .help("Writes progress to stderr in the given format. Batch commands default to bar when run in a terminal"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .global(true)
+                .help("Doesn't show the progress bar"),
+        )
+        .arg(
+            Arg::with_name("temperature-unit")
+                .long("temperature-unit")
+                .takes_value(true)
+                .possible_values(&["c", "f", "k"])
+                .default_value("c")
+                .global(true)
+                .help("Sets the unit of temperatures in the output, filters, and temperature bands"),
+        )
+        .arg(
+            Arg::with_name("permissive")
+                .long("permissive")
+                .global(true)
+                .help("Skips malformed maker note entries, with a warning, instead of failing the file"),
+        )
+        .arg(
+            Arg::with_name("max-value-size")
+                .long("max-value-size")
+                .takes_value(true)
+                .value_name("BYTES")
+                .global(true)
+                .help("Rejects maker note entries larger than this, as malformed [default: 1048576]"),
+        )
+        .arg(
+            Arg::with_name("cache")
+                .long("cache")
+                .global(true)
+                .help("Reuses the metadata of files which haven't changed since the last run with --cache, from the XDG cache directory"),
+        )
+        .arg(
+            Arg::with_name("max-scan-bytes")
+                .long("max-scan-bytes")
+                .takes_value(true)
+                .value_name("BYTES")
+                .global(true)
+                .help("Fails files whose metadata isn't within this many bytes of the start, instead of reading further"),
+        )
+        .arg(
+            Arg::with_name("allow-estimated")
+                .long("allow-estimated")
+                .global(true)
+                .help("Lets filters match frames by estimated values, such as a temperature the camera didn't record"),
+        )
+        .arg(filter_arg())
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .takes_value(true)
+                .possible_values(&["pretty", "debug", "json", "ndjson", "exiftool-json", "fits"])
+                .default_value("pretty")
+                .help("Sets the output format. ndjson writes one JSON object per file as soon as it's read, including for files which fail"),
+        )
+        .arg(
+            Arg::with_name("summary")
+                .long("summary")
+                .help("Writes counts of the camera models, sensitivities, temperatures, exposure times and failures to stderr at the end"),
+        )
+        .arg(
+            Arg::with_name("fits-sidecar")
+                .long("fits-sidecar")
+                .help("Writes the FITS header keywords to a .hdr file next to the input file"),
+        )
+        .arg(
+            Arg::with_name("write-xmp")
+                .long("write-xmp")
+                .help("Writes the extracted metadata to a .xmp sidecar next to the input file"),
+        )
+        .arg(
+            Arg::with_name("audit")
+                .long("audit")
+                .help("Reports tags which disagree about the exposure time or aperture"),
+        )
         .arg(
             Arg::with_name("INPUT_FILE")
-                .help("Sets the input file to use")
+                .help("Sets the input files to use, or - to read from stdin. May also be a URL when built with the 'remote' feature")
                 .required(true)
+                .multiple(true)
                 .index(1),
         )
-        .get_matches();
+        .subcommand(
+            SubCommand::with_name("extract-preview")
+                .about("Extracts the embedded JPEG preview or thumbnail from an image")
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .required(true)
+                        .help("Sets the file to write the JPEG to"),
+                )
+                .arg(
+                    Arg::with_name("INPUT_FILE")
+                        .help("Sets the input file to use")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(report)
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Compares the metadata of two frames, marking the differences which matter for calibration")
+                .arg(
+                    Arg::with_name("max-temperature-delta")
+                        .long("max-temperature-delta")
+                        .takes_value(true)
+                        .value_name("DEGREES")
+                        .default_value("1")
+                        .help("Marks the temperatures as different if they're further apart than this, in the unit set by --temperature-unit"),
+                )
+                .arg(
+                    Arg::with_name("A")
+                        .help("Sets the first frame")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("B")
+                        .help("Sets the second frame")
+                        .required(true)
+                        .index(2),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("rename")
+                .about("Renames files based on their metadata")
+                .arg(filter_arg())
+                .arg(
+                    Arg::with_name("template")
+                        .long("template")
+                        .takes_value(true)
+                        .default_value(rename::DEFAULT_TEMPLATE)
+                        .help("Sets the new file name. Fields: {model} {serial} {iso} {exposure} {temp} {temp_bin} {datetime} {date} {name} {ext}"),
+                )
+                .arg(
+                    Arg::with_name("temperature-bin")
+                        .long("temperature-bin")
+                        .takes_value(true)
+                        .value_name("DEGREES")
+                        .default_value("2")
+                        .help("Sets the width of the temperature ranges used by {temp_bin}"),
+                )
+                .arg(
+                    Arg::with_name("copy")
+                        .long("copy")
+                        .help("Copies the files instead of renaming them"),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Prints the new names without changing any files"),
+                )
+                .arg(
+                    Arg::with_name("INPUT_FILE")
+                        .help("Sets the input files to use")
+                        .required(true)
+                        .multiple(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("organize")
+                .about("Moves files in to directories based on their metadata")
+                .arg(filter_arg())
+                .arg(
+                    Arg::with_name("into")
+                        .long("into")
+                        .takes_value(true)
+                        .value_name("DIRECTORY")
+                        .required_unless("undo")
+                        .help("Sets the root of the directory hierarchy"),
+                )
+                .arg(
+                    Arg::with_name("template")
+                        .long("template")
+                        .takes_value(true)
+                        .default_value(rename::DEFAULT_ORGANIZE_TEMPLATE)
+                        .help("Sets the path of each file, relative to --into. Uses the same fields as rename"),
+                )
+                .arg(
+                    Arg::with_name("temperature-bin")
+                        .long("temperature-bin")
+                        .takes_value(true)
+                        .value_name("DEGREES")
+                        .default_value("2")
+                        .help("Sets the width of the temperature ranges used by {temp_bin}"),
+                )
+                .arg(
+                    Arg::with_name("copy")
+                        .long("copy")
+                        .help("Copies the files instead of moving them"),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Prints the new paths without changing any files"),
+                )
+                .arg(
+                    Arg::with_name("undo-log")
+                        .long("undo-log")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("Records the moved files, so that they can be restored with --undo"),
+                )
+                .arg(
+                    Arg::with_name("undo")
+                        .long("undo")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .conflicts_with_all(&["into", "INPUT_FILE"])
+                        .help("Restores the files recorded in the undo log"),
+                )
+                .arg(
+                    Arg::with_name("INPUT_FILE")
+                        .help("Sets the input files to use")
+                        .required_unless("undo")
+                        .multiple(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("duplicates")
+                .about("Finds files which are copies of each other, or which record the same exposure")
+                .arg(filter_arg())
+                .arg(
+                    Arg::with_name("INPUT_FILE")
+                        .help("Sets the input files to use")
+                        .required(true)
+                        .multiple(true)
+                        .index(1),
+                ),
+        );
+    #[cfg(feature = "server")]
+    let app = app.subcommand(
+        SubCommand::with_name("serve")
+            .about("Serves metadata extraction over HTTP")
+            .arg(
+                Arg::with_name("ADDRESS")
+                    .help("Sets the address to listen on")
+                    .default_value("127.0.0.1:8080")
+                    .index(1),
+            ),
+    );
+    #[cfg(feature = "gpl")]
+    let app = app.subcommand(
+        SubCommand::with_name("stats")
+            .about("Prints statistics of the raw sensor data for each color channel")
+            .arg(
+                Arg::with_name("INPUT_FILE")
+                    .help("Sets the input files to use")
+                    .required(true)
+                    .multiple(true)
+                    .index(1),
+            ),
+    );
+    #[cfg(feature = "gpl")]
+    let app = app.subcommand(
+        SubCommand::with_name("bias")
+            .about("Prints the bias level measured from the masked pixels for each color channel")
+            .arg(
+                Arg::with_name("INPUT_FILE")
+                    .help("Sets the input files to use")
+                    .required(true)
+                    .multiple(true)
+                    .index(1),
+            ),
+    );
+    #[cfg(feature = "gpl")]
+    let app = app.subcommand(
+        SubCommand::with_name("hot-pixels")
+            .about("Finds hot and stuck pixels in a dark frame")
+            .arg(
+                Arg::with_name("sigma")
+                    .long("sigma")
+                    .takes_value(true)
+                    .default_value("5")
+                    .help("Sets how many standard deviations above the median a pixel must be"),
+            )
+            .arg(
+                Arg::with_name("format")
+                    .long("format")
+                    .takes_value(true)
+                    .possible_values(&["json", "text"])
+                    .default_value("json")
+                    .help("Sets the format of the hot pixel map"),
+            )
+            .arg(
+                Arg::with_name("INPUT_FILE")
+                    .help("Sets the dark frame to use")
+                    .required(true)
+                    .index(1),
+            ),
+    );
+    #[cfg(feature = "gpl")]
+    let app = app.subcommand(
+        SubCommand::with_name("stack")
+            .about("Combines dark frames in to a master dark")
+            .arg(
+                Arg::with_name("method")
+                    .long("method")
+                    .takes_value(true)
+                    .possible_values(&["mean", "median", "sigma-clip"])
+                    .default_value("sigma-clip")
+                    .help("Sets how the frames are combined"),
+            )
+            .arg(
+                Arg::with_name("kappa")
+                    .long("kappa")
+                    .takes_value(true)
+                    .default_value("3")
+                    .help("Sets how many standard deviations from the mean a value is rejected by sigma-clip"),
+            )
+            .arg(
+                Arg::with_name("bit-depth")
+                    .long("bit-depth")
+                    .takes_value(true)
+                    .possible_values(&["16", "32"])
+                    .default_value("32")
+                    .help("Writes 16-bit integer or 32-bit floating point samples"),
+            )
+            .arg(
+                Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .required(true)
+                    .help("Sets the master dark to write. The format is chosen by the extension: .fits or .tif"),
+            )
+            .arg(
+                Arg::with_name("INPUT_FILE")
+                    .help("Sets the dark frames to use")
+                    .required(true)
+                    .multiple(true)
+                    .index(1),
+            ),
+    );
+    #[cfg(feature = "watch")]
+    let app = app.subcommand(
+        SubCommand::with_name("watch")
+            .about("Prints the metadata of new frames as they are written to a directory")
+            .arg(
+                Arg::with_name("max-drift")
+                    .long("max-drift")
+                    .takes_value(true)
+                    .value_name("DEGREES")
+                    .help("Warns when the sensor temperature drifts further than this from the first frame, in the unit set by --temperature-unit"),
+            )
+            .arg(
+                Arg::with_name("output")
+                    .long("output")
+                    .takes_value(true)
+                    .possible_values(&["pretty", "debug"])
+                    .default_value("pretty")
+                    .help("Sets the output format"),
+            )
+            .arg(
+                Arg::with_name("DIRECTORY")
+                    .help("Sets the directory to watch")
+                    .required(true)
+                    .index(1),
+            ),
+    );
+    let matches = app.get_matches();
 
     let verbosity: u64 = matches.occurrences_of("v");
     let log_level = match verbosity {
@@ -39,10 +782,484 @@ fn main() -> Result<(), Error> {
         .filter_level(log_level)
         .init();
 
-    let path = matches.value_of("INPUT_FILE").unwrap();
+    #[cfg(feature = "server")]
+    {
+        if let Some(serve_matches) = matches.subcommand_matches("serve") {
+            return server::serve(
+                serve_matches.value_of("ADDRESS").unwrap(),
+                metadata_parser(serve_matches)?,
+            );
+        }
+    }
+    #[cfg(feature = "gpl")]
+    {
+        if let Some(stats_matches) = matches.subcommand_matches("stats") {
+            let paths: Vec<&str> = stats_matches.values_of("INPUT_FILE").unwrap().collect();
+            for path in paths.iter() {
+                let frame = raw::RawFrame::decode(path)?;
+                if paths.len() > 1 {
+                    println!("{}:", path);
+                }
+                print!("{}", stats::format_stats(&stats::channel_stats(&frame)));
+            }
+            return Ok(());
+        }
+    }
+    #[cfg(feature = "gpl")]
+    {
+        if let Some(bias_matches) = matches.subcommand_matches("bias") {
+            let paths: Vec<&str> = bias_matches.values_of("INPUT_FILE").unwrap().collect();
+            for path in paths.iter() {
+                let frame = raw::RawFrame::decode(path)?;
+                if paths.len() > 1 {
+                    println!("{}:", path);
+                }
+                print!(
+                    "{}",
+                    stats::format_bias(&stats::masked_stats(&frame)?, &frame)
+                );
+            }
+            return Ok(());
+        }
+    }
+    #[cfg(feature = "gpl")]
+    {
+        if let Some(hot_matches) = matches.subcommand_matches("hot-pixels") {
+            let path = hot_matches.value_of("INPUT_FILE").unwrap();
+            let sigma = hot_matches.value_of("sigma").unwrap();
+            let sigma = sigma
+                .parse::<f64>()
+                .map_err(|_| Error::InvalidArgument(format!("Invalid sigma: {}", sigma)))?;
+            let frame = raw::RawFrame::decode(path)?;
+            // The map is still useful without the metadata, for example for cameras which don't
+            // record temperature
+            let metadata = metadata_parser(hot_matches)?.read_file(path).ok();
+            let map = hot_pixels::find_hot_pixels(&frame, metadata.as_ref(), sigma);
+            match hot_matches.value_of("format").unwrap() {
+                "text" => print!("{}", hot_pixels::format_text(&map)),
+                _ => println!("{}", serde_json::to_string_pretty(&map).unwrap()),
+            }
+            return Ok(());
+        }
+    }
+    #[cfg(feature = "gpl")]
+    {
+        if let Some(stack_matches) = matches.subcommand_matches("stack") {
+            let paths: Vec<&str> = stack_matches.values_of("INPUT_FILE").unwrap().collect();
+            let output = stack_matches.value_of("output").unwrap();
+            let extension = Path::new(output)
+                .extension()
+                .and_then(|x| x.to_str())
+                .map(|x| x.to_lowercase());
+            let write_fits = match extension.as_deref() {
+                Some("fits") | Some("fit") | Some("fts") => true,
+                Some("tif") | Some("tiff") => false,
+                _ => {
+                    return Err(Error::InvalidArgument(format!(
+                        "Unknown output format: {}. Expected .fits or .tif",
+                        output
+                    )))
+                }
+            };
+            let combine =
+                match stack_matches.value_of("method").unwrap() {
+                    "mean" => stack::Combine::Mean,
+                    "median" => stack::Combine::Median,
+                    _ => {
+                        let kappa = stack_matches.value_of("kappa").unwrap();
+                        stack::Combine::SigmaClip(kappa.parse::<f64>().map_err(|_| {
+                            Error::InvalidArgument(format!("Invalid kappa: {}", kappa))
+                        })?)
+                    }
+                };
+            let format = match stack_matches.value_of("bit-depth").unwrap() {
+                "16" => stack::SampleFormat::U16,
+                _ => stack::SampleFormat::F32,
+            };
+
+            let mut progress =
+                Progress::new("stack", paths.len(), progress_format(stack_matches, true));
+            let mut frames = vec![];
+            for path in paths.iter().copied() {
+                let frame = raw::RawFrame::decode(path);
+                progress.file_done(path, frame.as_ref().err());
+                frames.push(frame?);
+            }
+            progress.finish();
+            let master = stack::stack(&frames, combine)?;
+            // Exposure time, temperature, etc are recorded from the first frame
+            let metadata = metadata_parser(stack_matches)?.read_file(paths[0]).ok();
+            let mut file = std::io::BufWriter::new(std::fs::File::create(output)?);
+            if write_fits {
+                stack::write_fits(&mut file, &master, format, metadata.as_ref())?;
+            } else {
+                stack::write_tiff(&mut file, &master, format)?;
+            }
+            return Ok(());
+        }
+    }
+    #[cfg(feature = "watch")]
+    {
+        if let Some(watch_matches) = matches.subcommand_matches("watch") {
+            return watch::watch(
+                Path::new(watch_matches.value_of("DIRECTORY").unwrap()),
+                parse_temperature_delta(watch_matches.value_of("max-drift"))?,
+                parse_temperature_unit(watch_matches),
+                watch_matches.value_of("output").unwrap() == "pretty",
+                metadata_parser(watch_matches)?,
+            );
+        }
+    }
+
+    if let Some(preview_matches) = matches.subcommand_matches("extract-preview") {
+        let preview = metadata_parser(preview_matches)?
+            .read_preview(preview_matches.value_of("INPUT_FILE").unwrap())?;
+        std::fs::write(preview_matches.value_of("output").unwrap(), preview)?;
+        return Ok(());
+    }
+
+    if let Some(diff_matches) = matches.subcommand_matches("diff") {
+        let parser = metadata_parser(diff_matches)?;
+        let a = diff_matches.value_of("A").unwrap();
+        let b = diff_matches.value_of("B").unwrap();
+        let max_delta =
+            parse_temperature_delta(diff_matches.value_of("max-temperature-delta"))?.unwrap();
+        print!(
+            "{}",
+            compare::format_diff(
+                (a, &parser.read_file(a)?),
+                (b, &parser.read_file(b)?),
+                parse_temperature_unit(diff_matches),
+                max_delta
+            )
+        );
+        return Ok(());
+    }
+
+    if let Some(rename_matches) = matches.subcommand_matches("rename") {
+        let filter = parse_filter(rename_matches)?;
+        let paths: Vec<&str> = rename_matches.values_of("INPUT_FILE").unwrap().collect();
+        let mut progress =
+            Progress::new("rename", paths.len(), progress_format(rename_matches, true));
+        let (files, failure) = read_files(
+            &metadata_parser(rename_matches)?,
+            &paths,
+            filter.as_ref(),
+            &mut progress,
+        );
+        let template = rename::Template {
+            pattern: rename_matches.value_of("template").unwrap(),
+            temperature_bin: parse_temperature_bin(rename_matches.value_of("temperature-bin"))?,
+        };
+        let renames = rename::plan(&files, &template, |path| {
+            path.parent().unwrap_or_else(|| Path::new("")).to_path_buf()
+        })?;
+        for rename in renames.iter() {
+            println!("{} -> {}", rename.from.display(), rename.to.display());
+        }
+        if !rename_matches.is_present("dry-run") {
+            let mut log = rename::UndoLog::new(rename_matches.is_present("copy"));
+            rename::apply(&renames, &mut log)?;
+        }
+        if let Some(code) = failure {
+            std::process::exit(code);
+        }
+        return Ok(());
+    }
+
+    if let Some(organize_matches) = matches.subcommand_matches("organize") {
+        let filter = parse_filter(organize_matches)?;
+        if let Some(undo) = organize_matches.value_of("undo") {
+            let log: rename::UndoLog = serde_json::from_str(&std::fs::read_to_string(undo)?)
+                .map_err(|err| Error::InvalidArgument(format!("Invalid undo log: {}", err)))?;
+            return rename::undo(&log);
+        }
+        let paths: Vec<&str> = organize_matches.values_of("INPUT_FILE").unwrap().collect();
+        let into = Path::new(organize_matches.value_of("into").unwrap());
+        let mut progress = Progress::new(
+            "organize",
+            paths.len(),
+            progress_format(organize_matches, true),
+        );
+        let (files, failure) = read_files(
+            &metadata_parser(organize_matches)?,
+            &paths,
+            filter.as_ref(),
+            &mut progress,
+        );
+        let template = rename::Template {
+            pattern: organize_matches.value_of("template").unwrap(),
+            temperature_bin: parse_temperature_bin(organize_matches.value_of("temperature-bin"))?,
+        };
+        let renames = rename::plan(&files, &template, |_| into.to_path_buf())?;
+        for rename in renames.iter() {
+            println!("{} -> {}", rename.from.display(), rename.to.display());
+        }
+        if !organize_matches.is_present("dry-run") {
+            let mut log = rename::UndoLog::new(organize_matches.is_present("copy"));
+            let result = rename::apply(&renames, &mut log);
+            // Written even if a move failed, so that the files moved before it can be put back
+            if let Some(path) = organize_matches.value_of("undo-log") {
+                std::fs::write(path, serde_json::to_string_pretty(&log).unwrap())?;
+            }
+            result?;
+        }
+        if let Some(code) = failure {
+            std::process::exit(code);
+        }
+        return Ok(());
+    }
+
+    if let Some(duplicates_matches) = matches.subcommand_matches("duplicates") {
+        let filter = parse_filter(duplicates_matches)?;
+        let paths: Vec<&str> = duplicates_matches
+            .values_of("INPUT_FILE")
+            .unwrap()
+            .collect();
+        let mut progress = Progress::new(
+            "duplicates",
+            paths.len(),
+            progress_format(duplicates_matches, true),
+        );
+        let (files, failure) = read_files(
+            &metadata_parser(duplicates_matches)?,
+            &paths,
+            filter.as_ref(),
+            &mut progress,
+        );
+        // Files which can't be read are still compared by content, unless they're filtered out
+        let compared: Vec<&str> = match &filter {
+            Some(_) => files.iter().map(|(path, _)| *path).collect(),
+            None => paths.clone(),
+        };
+        print!(
+            "{}",
+            duplicates::format_duplicates(&duplicates::find_duplicates(&compared, &files)?)
+        );
+        if let Some(code) = failure {
+            std::process::exit(code);
+        }
+        return Ok(());
+    }
+
+    if let Some(report_matches) = matches.subcommand_matches("report") {
+        #[cfg(feature = "gpl")]
+        {
+            if let Some(dark_matches) = report_matches.subcommand_matches("dark-current") {
+                let filter = parse_filter(dark_matches)?;
+                let paths: Vec<&str> = dark_matches.values_of("INPUT_FILE").unwrap().collect();
+                let parser = metadata_parser(dark_matches)?;
+                let mut progress = Progress::new(
+                    "report dark-current",
+                    paths.len(),
+                    progress_format(dark_matches, true),
+                );
+                let mut frames = vec![];
+                let mut failure = None;
+                for path in paths.iter().copied() {
+                    let result = parser.read_file(path).and_then(|metadata| {
+                        if matches!(&filter, Some(x) if !x.matches(&metadata)) {
+                            return Ok(None);
+                        }
+                        let frame = raw::RawFrame::decode(path)?;
+                        Ok(Some((path, metadata, stats::mean_level(&frame))))
+                    });
+                    progress.file_done(path, result.as_ref().err());
+                    match result {
+                        Ok(Some(frame)) => frames.push(frame),
+                        Ok(None) => {}
+                        Err(err) => {
+                            eprintln!("{}: {}", path, err);
+                            failure.get_or_insert(err.exit_code());
+                        }
+                    }
+                }
+                progress.finish();
+                print!(
+                    "{}",
+                    report::dark_current_report(&frames, parse_temperature_unit(dark_matches))
+                );
+                if let Some(code) = failure {
+                    std::process::exit(code);
+                }
+                return Ok(());
+            }
+        }
+        if let Some(html_matches) = report_matches.subcommand_matches("html") {
+            let filter = parse_filter(html_matches)?;
+            let paths: Vec<&str> = html_matches.values_of("INPUT_FILE").unwrap().collect();
+            let mut progress = Progress::new(
+                "report html",
+                paths.len(),
+                progress_format(html_matches, true),
+            );
+            let (files, failure) = read_files(
+                &metadata_parser(html_matches)?,
+                &paths,
+                filter.as_ref(),
+                &mut progress,
+            );
+            std::fs::write(
+                html_matches.value_of("output").unwrap(),
+                html::html_report(&files, parse_temperature_unit(html_matches)),
+            )?;
+            if let Some(code) = failure {
+                std::process::exit(code);
+            }
+            return Ok(());
+        }
+        if let Some(histogram_matches) = report_matches.subcommand_matches("histogram") {
+            let filter = parse_filter(histogram_matches)?;
+            let paths: Vec<&str> = histogram_matches.values_of("INPUT_FILE").unwrap().collect();
+            let field =
+                report::NumericField::parse(histogram_matches.value_of("field").unwrap()).unwrap();
+            let bin = histogram_matches.value_of("bin").unwrap();
+            let bin = bin
+                .parse::<f64>()
+                .map_err(|_| Error::InvalidArgument(format!("Invalid bin width: {}", bin)))?;
+            let mut progress = Progress::new(
+                "report histogram",
+                paths.len(),
+                progress_format(histogram_matches, true),
+            );
+            let (files, failure) = read_files(
+                &metadata_parser(histogram_matches)?,
+                &paths,
+                filter.as_ref(),
+                &mut progress,
+            );
+            let histogram = report::histogram_report(
+                &files,
+                field,
+                bin,
+                parse_temperature_unit(histogram_matches),
+                histogram_matches.value_of("format").unwrap() == "json",
+            )?;
+            println!("{}", histogram.trim_end());
+            if let Some(code) = failure {
+                std::process::exit(code);
+            }
+            return Ok(());
+        }
+        if let Some(temperature_matches) = report_matches.subcommand_matches("temperature") {
+            let filter = parse_filter(temperature_matches)?;
+            let paths: Vec<&str> = temperature_matches
+                .values_of("INPUT_FILE")
+                .unwrap()
+                .collect();
+            let unit = parse_temperature_unit(temperature_matches);
+            let band_min = parse_temperature(temperature_matches.value_of("band-min"))?
+                .map(|x| unit.to_celsius(x.0));
+            let band_max = parse_temperature(temperature_matches.value_of("band-max"))?
+                .map(|x| unit.to_celsius(x.0));
+            let mut progress = Progress::new(
+                "report temperature",
+                paths.len(),
+                progress_format(temperature_matches, true),
+            );
+            let (files, failure) = read_files(
+                &metadata_parser(temperature_matches)?,
+                &paths,
+                filter.as_ref(),
+                &mut progress,
+            );
+            print!(
+                "{}",
+                report::temperature_report(&files, band_min, band_max, unit)
+            );
+            if let Some(code) = failure {
+                std::process::exit(code);
+            }
+        }
+        return Ok(());
+    }
+
+    let paths: Vec<&str> = matches.values_of("INPUT_FILE").unwrap().collect();
+    let output = matches.value_of("output").unwrap();
+    let filter = parse_filter(&matches)?;
+    let unit = parse_temperature_unit(&matches);
+    let parser = metadata_parser(&matches)?;
+    let mut json_files = vec![];
+    let mut failure = None;
+    // Output which is written as each file is read would break up the bar
+    let bar_by_default = output == "json" || output == "exiftool-json";
+    let mut progress = Progress::new(
+        "metadata",
+        paths.len(),
+        progress_format(&matches, bar_by_default),
+    );
+    let mut summary = Summary::default();
+    for path in paths.iter().copied() {
+        let result = process_file(&parser, path, &matches, filter.as_ref());
+        progress.file_done(path, result.as_ref().err());
+        match &result {
+            Ok(Some(metadata)) => summary.add(metadata),
+            Ok(None) => {}
+            Err(err) => summary.add_failure(err),
+        }
+        let metadata = match result {
+            Ok(Some(metadata)) => metadata,
+            Ok(None) => continue,
+            // Errors are part of the stream, so that consumers see every input
+            Err(err) if output == "ndjson" => {
+                println!(
+                    "{}",
+                    json!({"path": path, "error": err.to_string(), "code": err.code()})
+                );
+                failure.get_or_insert(err.exit_code());
+                continue;
+            }
+            // Errors are reported with the rest of the output, so that they can be parsed too
+            Err(err) if output == "json" => {
+                eprintln!(
+                    "{}",
+                    json!({"path": path, "error": err.to_string(), "code": err.code()})
+                );
+                failure.get_or_insert(err.exit_code());
+                continue;
+            }
+            // With --summary, a single file is reported like a batch, so that the summary is
+            // still printed
+            Err(err) if paths.len() == 1 && !matches.is_present("summary") => {
+                progress.finish();
+                return Err(err);
+            }
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                failure.get_or_insert(err.exit_code());
+                continue;
+            }
+        };
+        match output {
+            "json" | "exiftool-json" => json_files.push((path, metadata)),
+            "ndjson" => println!("{}", json!({"path": path, "metadata": metadata})),
+            "fits" => print!("{}", fits::format_header(&metadata)),
+            "pretty" if paths.len() == 1 => print!("{}", pretty::format_metadata(&metadata, unit)),
+            "pretty" => println!("{}:\n{}", path, pretty::format_metadata(&metadata, unit)),
+            _ if paths.len() == 1 => println!("{:?}", metadata),
+            _ => println!("{}: {:?}", path, metadata),
+        }
+    }
+
+    progress.finish();
 
-    let parser = MetadataParser::new();
-    println!("{:?}", parser.read_file(path)?);
+    match output {
+        "json" => {
+            let files: Vec<serde_json::Value> = json_files
+                .iter()
+                .map(|(path, metadata)| json!({"path": path, "metadata": metadata}))
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&files).unwrap());
+        }
+        "exiftool-json" => println!("{}", exiftool::format_json(&json_files)),
+        _ => {}
+    }
+    if matches.is_present("summary") {
+        eprint!("{}", summary.format(unit));
+    }
+    if let Some(code) = failure {
+        std::process::exit(code);
+    }
 
     Ok(())
 }
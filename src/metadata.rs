@@ -1,9 +1,36 @@
+use crate::apex::get_shutter_speed;
+use crate::audit::{audit_exposure, Mismatch};
+use crate::cache::MetadataCache;
+use crate::canon_video;
 use crate::error::Error;
-use crate::ifd::parse_canon_makernote;
-use exif::{Exif, In, Rational, Tag, Value};
+use crate::heif;
+use crate::ifd::{
+    parse_canon_makernote, parse_fujifilm_makernote, parse_nikon_makernote,
+    parse_olympus_makernote, parse_panasonic_makernote, parse_pentax_makernote, OlympusMakerNote,
+    PentaxMakerNote,
+};
+use crate::jxl;
+use crate::preview::{find_preview, get_thumbnail, Thumbnail};
+use crate::raf;
+use crate::tiff::{Ifd, IfdEntry, ParseOptions, SkippedEntry};
+use crate::units::{Celsius, Fraction, Seconds};
+use crate::vendor_tiff;
+use crate::xmp::{read_sidecar, XmpMetadata};
+use exif::{DateTime, Exif, In, Rational, Tag, Value};
+use log::{log, trace, Level};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+#[cfg(not(feature = "mmap"))]
+use std::io::Read;
+use std::io::{BufRead, Cursor, Seek};
 use std::path::Path;
 use std::str::FromStr;
+use std::sync::Mutex;
 
+// "Unknown" in EXIF 2.3. Used for ISOSpeedRatings, which is all that EXIF < 2.3 records, since it
+// doesn't say which standard the sensitivity was measured by
+const SENSITIVITY_TYPE_LEGACY: u16 = 0;
 const SENSITIVITY_TYPE_SOS: u16 = 1;
 const SENSITIVITY_TYPE_REI: u16 = 2;
 const SENSITIVITY_TYPE_ISO: u16 = 3;
@@ -12,145 +39,397 @@ const SENSITIVITY_TYPE_SOS_AND_ISO: u16 = 5;
 const SENSITIVITY_TYPE_REI_AND_ISO: u16 = 6;
 const SENSITIVITY_TYPE_SOS_AND_REI_AND_ISO: u16 = 7;
 
+// Tags tried, in order, when SensitivityType is missing. ISOSpeedRatings is the last resort
+const SENSITIVITY_PROBE_ORDER: &[(Tag, &str, u16)] = &[
+    (Tag::ISOSpeed, "ISOSpeed", SENSITIVITY_TYPE_ISO),
+    (
+        Tag::RecommendedExposureIndex,
+        "RecommendedExposureIndex",
+        SENSITIVITY_TYPE_REI,
+    ),
+    (
+        Tag::StandardOutputSensitivity,
+        "StandardOutputSensitivity",
+        SENSITIVITY_TYPE_SOS,
+    ),
+];
+
+const TAG_CANON_CAMERA_SETTINGS: u16 = 1;
 const TAG_CANON_SHOTINFO: u16 = 4;
+const TAG_CANON_CUSTOM_FUNCTIONS: u16 = 0x99;
+
+const CAMERA_SETTINGS_CAMERA_ISO: usize = 16;
+// CameraISO values. Newer cameras record manual ISOs as the speed with this flag set
+const CAMERA_ISO_AUTO_HIGH: u16 = 14;
+const CAMERA_ISO_AUTO: u16 = 15;
+const CAMERA_ISO_MANUAL_MIN: u16 = 16;
+const CAMERA_ISO_MANUAL_MAX: u16 = 20;
+const CAMERA_ISO_SPEED_FLAG: u16 = 0x4000;
+const CAMERA_ISO_UNKNOWN: u16 = 0x7fff;
+
+// CustomFunctions is a list of groups, each with a header of (group number, size in bytes, number
+// of functions), followed by its functions as (function ID, number of values, values...). All
+// fields are 32 bits. The first two words are the total size and the number of groups
+const CUSTOM_FUNCTIONS_HEADER_WORDS: usize = 2;
+const CUSTOM_FUNCTIONS_GROUP_HEADER_WORDS: usize = 3;
+const CUSTOM_FUNCTION_LONG_EXPOSURE_NOISE_REDUCTION: u32 = 0x201;
+const LONG_EXPOSURE_NOISE_REDUCTION_OFF: u32 = 0;
+const LONG_EXPOSURE_NOISE_REDUCTION_ON: u32 = 2;
 
 const SHOTINFO_CAMERA_TEMPERATURE: usize = 12;
 
-#[derive(Debug)]
+const TAG_NIKON_SERIAL_NUMBER: u16 = 0x1d;
+const TAG_NIKON_SHUTTER_COUNT: u16 = 0xa7;
+const TAG_NIKON_ISO_SELECTION: u16 = 0xf;
+const TAG_NIKON_ISO_INFO: u16 = 0x25;
+// "FPNR" (fixed pattern noise reduction) when long exposure noise reduction was applied
+const TAG_NIKON_NOISE_REDUCTION: u16 = 0x95;
+// ISOExpansion within ISOInfo. Zero when off, otherwise it encodes the Hi or Lo step
+const NIKON_ISO_EXPANSION: std::ops::Range<usize> = 4..6;
+
+const TAG_FUJIFILM_SERIAL_NUMBER: u16 = 0x10;
+const TAG_FUJIFILM_IMAGE_COUNT: u16 = 0x1438;
+// The top bit of ImageCount is a flag, not part of the count
+const FUJIFILM_IMAGE_COUNT_MASK: u16 = 0x7fff;
+
+// Only written by older cameras. Newer ones record it in FocusInfo instead
+const TAG_OLYMPUS_SENSOR_TEMPERATURE: u16 = 0x1007;
+const TAG_OLYMPUS_EQUIPMENT_SERIAL_NUMBER: u16 = 0x101;
+// Two values, of which the first is the sensor temperature
+const TAG_OLYMPUS_FOCUS_INFO_SENSOR_TEMPERATURE: u16 = 0x1500;
+// Bit masks whose lowest bit is set when long exposure noise reduction was applied. Newer cameras
+// record it in CameraSettings, and older ones in ImageProcessing
+const TAG_OLYMPUS_CAMERA_SETTINGS_NOISE_REDUCTION: u16 = 0x50a;
+const TAG_OLYMPUS_IMAGE_PROCESSING_NOISE_REDUCTION: u16 = 0x1010;
+const OLYMPUS_NOISE_REDUCTION_FLAG: u16 = 0x1;
+
+const TAG_PANASONIC_INTERNAL_SERIAL_NUMBER: u16 = 0x25;
+
+const TAG_PENTAX_CAMERA_TEMPERATURE: u16 = 0x47;
+const TAG_PENTAX_SERIAL_NUMBER: u16 = 0x229;
+const TAG_PENTAX_NOISE_REDUCTION: u16 = 0x49;
+// Index in TempInfo of a second camera temperature, in tenths of a degree C
+const PENTAX_TEMP_INFO_CAMERA_TEMPERATURE: usize = 12;
+
+// Sensor temperatures outside of this range, in C, are most likely misread
+const PLAUSIBLE_TEMPERATURE_MIN: f32 = -40.0;
+const PLAUSIBLE_TEMPERATURE_MAX: f32 = 80.0;
+
+// Amount of the file initially read. Progressively more is read until the metadata can be parsed
+const INITIAL_READ_BYTES: u64 = 64 * 1024;
+
+// How the value of a field was obtained
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(in crate) enum Confidence {
+    // Read from the field which records it
+    Exact,
+    // Computed from another field, such as the exposure time from the APEX shutter speed
+    Derived,
+    // Approximated, because the camera doesn't record it exactly, such as a sensitivity from
+    // ISOSpeedRatings, which doesn't say which standard it follows
+    Estimated,
+    // Taken from an XMP sidecar, instead of the image
+    Overridden,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(in crate) struct FieldConfidence {
+    pub camera_model: Confidence,
+    pub camera_serial_number: Confidence,
+    pub sensor_sensitivity: Confidence,
+    pub exposure_time: Confidence,
+    pub temperature: Confidence,
+    pub capture_time: Confidence,
+}
+
+// Every sensitivity the camera recorded. Cameras may record several, for example both the SOS and
+// the ISO speed, and sensor_sensitivity is only one of them
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(in crate) struct Sensitivities {
+    pub standard_output_sensitivity: Option<u32>,
+    pub recommended_exposure_index: Option<u32>,
+    pub iso_speed: Option<u32>,
+    // Range of ISO speeds around iso_speed, from ISOSpeedLatitudeyyy and ISOSpeedLatitudezzz
+    pub iso_speed_latitude_yyy: Option<u32>,
+    pub iso_speed_latitude_zzz: Option<u32>,
+    // Whether the sensitivity is one of the camera's expanded (H or L) settings, beyond its native
+    // range. None if the maker note doesn't say
+    pub extended: Option<bool>,
+}
+
+impl Sensitivities {
+    // (name, value) of each recorded sensitivity
+    pub fn values(&self) -> Vec<(&'static str, u32)> {
+        let mut values = vec![];
+        if let Some(x) = self.standard_output_sensitivity {
+            values.push(("SOS", x));
+        }
+        if let Some(x) = self.recommended_exposure_index {
+            values.push(("REI", x));
+        }
+        if let Some(x) = self.iso_speed {
+            values.push(("ISO", x));
+        }
+        values
+    }
+}
+
+// Something which didn't prevent the metadata from being read, but may make it less accurate
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(in crate) enum Warning {
+    // The XMP sidecar has a sensitivity, but no SensitivityType
+    AssumedSensitivityType,
+    // EXIF < 2.3 only records ISOSpeedRatings, without a SensitivityType
+    LegacySensitivity,
+    // SensitivityType is missing, so the sensitivity was read from the first tag present
+    ProbedSensitivity(String),
+    // ExposureTime is missing, so it was computed from ShutterSpeedValue
+    ExposureFromShutterSpeed,
+    // BodySerialNumber is missing, so the maker note's serial number was used
+    SerialFromMakerNote,
+    // The vendor's maker note doesn't record a readable sensor temperature
+    TemperatureNotRecorded(String),
+    ImplausibleTemperature(Celsius),
+    // A malformed maker note entry was skipped in permissive mode. The tag is None if the entry was
+    // truncated before it
+    SkippedIfdEntry { tag: Option<u16>, reason: String },
+}
+
+impl Warning {
+    // Fallbacks are expected for some cameras, but implausible values usually mean a bug
+    pub fn level(&self) -> Level {
+        match self {
+            Warning::AssumedSensitivityType
+            | Warning::LegacySensitivity
+            | Warning::ProbedSensitivity(_)
+            | Warning::ExposureFromShutterSpeed
+            | Warning::SerialFromMakerNote => Level::Info,
+            Warning::TemperatureNotRecorded(_)
+            | Warning::ImplausibleTemperature(_)
+            | Warning::SkippedIfdEntry { .. } => Level::Warn,
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::AssumedSensitivityType => {
+                write!(f, "SensitivityType missing from sidecar, assuming ISO")
+            }
+            Warning::LegacySensitivity => write!(f, "Sensitivity read from legacy ISOSpeedRatings"),
+            Warning::ProbedSensitivity(x) => {
+                write!(f, "SensitivityType missing, sensitivity read from {}", x)
+            }
+            Warning::ExposureFromShutterSpeed => {
+                write!(f, "Exposure time computed from ShutterSpeedValue")
+            }
+            Warning::SerialFromMakerNote => write!(f, "Serial number read from maker note"),
+            Warning::TemperatureNotRecorded(x) => {
+                write!(f, "{} maker note does not record the sensor temperature", x)
+            }
+            Warning::ImplausibleTemperature(x) => {
+                write!(f, "Implausible sensor temperature: {} C", x)
+            }
+            Warning::SkippedIfdEntry {
+                tag: Some(tag),
+                reason,
+            } => write!(f, "Skipped maker note entry {:#06x}: {}", tag, reason),
+            Warning::SkippedIfdEntry { tag: None, reason } => {
+                write!(f, "Skipped maker note entries: {}", reason)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub(in crate) struct ImageMetadata {
-    camera_model: String,
-    camera_serial_number: String,
+    pub camera_make: String,
+    // Including the make, if the camera doesn't
+    pub camera_model: String,
+    pub camera_serial_number: String,
     // Generally ISO, but may also be REI or SOS
-    sensor_sensitivity: u32,
+    pub sensor_sensitivity: u32,
     // Type of sensitivity used, as defined for EXIF tag 0x8830
-    sensitivity_type: u16,
-    // Time in seconds
-    exposure_time: f32,
-    // Temperature in C
-    temperature: f32,
+    pub sensitivity_type: u16,
+    pub sensitivities: Sensitivities,
+    pub exposure_time: Seconds,
+    // Exact exposure time, when the file stores it as a rational
+    pub exposure_time_fraction: Option<Fraction>,
+    // None if the camera doesn't record it
+    pub temperature: Option<Celsius>,
+    // Another internal temperature, for cameras which record two, like newer Pentax bodies
+    pub secondary_temperature: Option<Celsius>,
+    // Time the exposure was taken, as an ISO 8601 timestamp without timezone
+    pub capture_time: Option<String>,
+    // Number of shutter actuations, including this exposure. Only recorded by some cameras
+    pub shutter_count: Option<u32>,
+    // Whether the camera chose the sensitivity itself (Auto ISO). None if the maker note doesn't
+    // say
+    pub auto_sensitivity: Option<bool>,
+    // Whether the camera subtracted its own dark frame (LENR). None if the maker note doesn't say
+    pub long_exposure_noise_reduction: Option<bool>,
+    pub thumbnail: Option<Thumbnail>,
+    pub confidence: FieldConfidence,
+    pub warnings: Vec<Warning>,
 }
 
-// Convert the given ascii data to an integer
+impl ImageMetadata {
+    // Standard which sensor_sensitivity is measured by
+    pub fn sensitivity_name(&self) -> &'static str {
+        match self.sensitivity_type {
+            SENSITIVITY_TYPE_SOS | SENSITIVITY_TYPE_SOS_AND_REI => "SOS",
+            SENSITIVITY_TYPE_REI => "REI",
+            _ => "ISO",
+        }
+    }
+}
+
+fn value_type(value: &Value) -> &'static str {
+    match value {
+        Value::Byte(_) => "Byte",
+        Value::Ascii(_) => "Ascii",
+        Value::Short(_) => "Short",
+        Value::Long(_) => "Long",
+        Value::Rational(_) => "Rational",
+        Value::SByte(_) => "SByte",
+        Value::Undefined(_, _) => "Undefined",
+        Value::SShort(_) => "SShort",
+        Value::SLong(_) => "SLong",
+        Value::SRational(_) => "SRational",
+        Value::Float(_) => "Float",
+        Value::Double(_) => "Double",
+        Value::Unknown(_, _, _) => "Unknown",
+    }
+}
+
+fn wrong_type(field_name: &'static str, expected: &'static str, value: &Value) -> Error {
+    Error::WrongType {
+        field: field_name,
+        expected,
+        actual: value_type(value).to_string(),
+    }
+}
+
+fn not_single_value(field_name: &'static str, count: usize) -> Error {
+    Error::WrongType {
+        field: field_name,
+        expected: "a single value",
+        actual: format!("{} values", count),
+    }
+}
+
+// Convert the given ascii digits, from the ExifVersion field, to an integer
 fn atoi(data: &[u8]) -> Result<u8, Error> {
+    let wrong_type = || Error::WrongType {
+        field: "ExifVersion",
+        expected: "2 ASCII digits",
+        actual: format!("{:?}", String::from_utf8_lossy(data)),
+    };
     if data.len() > 2 {
-        return Err(Error::InvalidData("Data too long".to_string()));
+        return Err(wrong_type());
     }
-    let utf8 = String::from_utf8(data.to_vec())
-        .map_err(|_| Error::InvalidData("Received non-UTF8 characters".to_string()))?;
-    u8::from_str(&utf8).map_err(|_| {
-        Error::InvalidData("Expected numeric ascii digits in ExifVersion field".to_string())
-    })
+    std::str::from_utf8(data)
+        .ok()
+        .and_then(|x| u8::from_str(x).ok())
+        .ok_or_else(wrong_type)
 }
 
 fn get_exif_version(exif: &Exif) -> Result<(u8, u8), Error> {
     let field = exif
         .get_field(Tag::ExifVersion, In::PRIMARY)
-        .ok_or_else(|| Error::InvalidData("Missing ExifVersion field".to_string()))?;
+        .ok_or(Error::MissingField {
+            field: "ExifVersion",
+        })?;
     if let Value::Undefined(data, _) = &field.value {
         if data.len() != 4 {
-            return Err(Error::InvalidData(
-                "Expected 4 bytes for ExifVersion field".to_string(),
-            ));
+            return Err(Error::WrongType {
+                field: "ExifVersion",
+                expected: "4 bytes",
+                actual: format!("{} bytes", data.len()),
+            });
         }
         Ok((atoi(&data[..2])?, atoi(&data[2..])?))
     } else {
-        Err(Error::InvalidData(
-            "Expected 'undefined' type data for ExifVersion".to_string(),
-        ))
+        Err(wrong_type("ExifVersion", "Undefined", &field.value))
     }
 }
 
-fn get_makernote(exif: &Exif) -> Result<Vec<u8>, Error> {
+// Returns the maker note, and its offset in the TIFF data
+fn get_makernote_with_offset(exif: &Exif) -> Result<(&[u8], usize), Error> {
     let field = exif
         .get_field(Tag::MakerNote, In::PRIMARY)
-        .ok_or_else(|| Error::InvalidData("Missing MakerNote field".to_string()))?;
-    if let Value::Undefined(data, _) = &field.value {
-        Ok(data.clone())
+        .ok_or(Error::MissingField { field: "MakerNote" })?;
+    if let Value::Undefined(data, offset) = &field.value {
+        Ok((data, *offset as usize))
     } else {
-        Err(Error::InvalidData(
-            "Expected 'undefined' type data for MakerNote".to_string(),
-        ))
+        Err(wrong_type("MakerNote", "Undefined", &field.value))
     }
 }
 
+fn get_makernote(exif: &Exif) -> Result<&[u8], Error> {
+    get_makernote_with_offset(exif).map(|(data, _)| data)
+}
+
 fn get_str_field(exif: &Exif, tag: Tag, field_name: &'static str) -> Result<String, Error> {
     let field = exif
         .get_field(tag, In::PRIMARY)
-        .ok_or_else(|| Error::InvalidData(format!("Missing {} field", field_name)))?;
+        .ok_or(Error::MissingField { field: field_name })?;
     if let Value::Ascii(data) = &field.value {
         if data.len() != 1 {
-            return Err(Error::InvalidData(format!(
-                "Expected single {} value",
-                field_name
-            )));
+            return Err(not_single_value(field_name, data.len()));
         }
-        String::from_utf8(data[0].clone())
-            .map_err(|_| Error::InvalidData(format!("Bad UTF-8 in {} field", field_name)))
+        String::from_utf8(data[0].clone()).map_err(|_| Error::WrongType {
+            field: field_name,
+            expected: "UTF-8",
+            actual: "invalid UTF-8".to_string(),
+        })
     } else {
-        Err(Error::InvalidData(format!(
-            "Expected u16 data for {} field",
-            field_name
-        )))
+        Err(wrong_type(field_name, "Ascii", &field.value))
     }
 }
 
 fn get_u16_field(exif: &Exif, tag: Tag, field_name: &'static str) -> Result<u16, Error> {
     let field = exif
         .get_field(tag, In::PRIMARY)
-        .ok_or_else(|| Error::InvalidData(format!("Missing {} field", field_name)))?;
+        .ok_or(Error::MissingField { field: field_name })?;
     if let Value::Short(data) = &field.value {
         if data.len() != 1 {
-            return Err(Error::InvalidData(format!(
-                "Expected single {} value",
-                field_name
-            )));
+            return Err(not_single_value(field_name, data.len()));
         }
         Ok(data[0])
     } else {
-        Err(Error::InvalidData(format!(
-            "Expected u16 data for {} field",
-            field_name
-        )))
+        Err(wrong_type(field_name, "Short", &field.value))
     }
 }
 
 fn get_u32_field(exif: &Exif, tag: Tag, field_name: &'static str) -> Result<u32, Error> {
     let field = exif
         .get_field(tag, In::PRIMARY)
-        .ok_or_else(|| Error::InvalidData(format!("Missing {} field", field_name)))?;
+        .ok_or(Error::MissingField { field: field_name })?;
     if let Value::Long(data) = &field.value {
         if data.len() != 1 {
-            return Err(Error::InvalidData(format!(
-                "Expected single {} value",
-                field_name
-            )));
+            return Err(not_single_value(field_name, data.len()));
         }
         Ok(data[0])
     } else {
-        Err(Error::InvalidData(format!(
-            "Expected u32 data for {} field",
-            field_name
-        )))
+        Err(wrong_type(field_name, "Long", &field.value))
     }
 }
 
 fn get_rational_field(exif: &Exif, tag: Tag, field_name: &'static str) -> Result<Rational, Error> {
     let field = exif
         .get_field(tag, In::PRIMARY)
-        .ok_or_else(|| Error::InvalidData(format!("Missing {} field", field_name)))?;
+        .ok_or(Error::MissingField { field: field_name })?;
     if let Value::Rational(data) = &field.value {
         if data.len() != 1 {
-            return Err(Error::InvalidData(format!(
-                "Expected single {} value",
-                field_name
-            )));
+            return Err(not_single_value(field_name, data.len()));
         }
         Ok(data[0])
     } else {
-        Err(Error::InvalidData(format!(
-            "Expected Rational data for {} field",
-            field_name
-        )))
+        Err(wrong_type(field_name, "Rational", &field.value))
     }
 }
 
@@ -158,31 +437,70 @@ fn get_make(exif: &Exif) -> Result<String, Error> {
     get_str_field(exif, Tag::Make, "Make")
 }
 
-fn get_model(exif: &Exif) -> Result<String, Error> {
-    let make = get_str_field(exif, Tag::Make, "Make")?;
-    let model = get_str_field(exif, Tag::Model, "Model")?;
+fn combine_make_and_model(make: String, model: String) -> String {
     if model.starts_with(&make) {
-        Ok(model)
+        model
     } else {
         let mut make_and_model = make;
         if !make_and_model.ends_with(' ') {
             make_and_model.push(' ');
         }
         make_and_model.push_str(&model);
-        Ok(make_and_model)
+        make_and_model
     }
 }
 
+fn get_model(exif: &Exif) -> Result<String, Error> {
+    let make = get_str_field(exif, Tag::Make, "Make")?;
+    let model = get_str_field(exif, Tag::Model, "Model")?;
+    Ok(combine_make_and_model(make, model))
+}
+
 fn get_serial_number(exif: &Exif) -> Result<String, Error> {
     get_str_field(exif, Tag::BodySerialNumber, "BodySerialNumber")
 }
 
-fn get_sensitivity(exif: &Exif) -> Result<(u32, u16), Error> {
+fn get_legacy_sensitivity(exif: &Exif) -> Result<u32, Error> {
+    get_u16_field(exif, Tag::PhotographicSensitivity, "ISOSpeedRatings").map(u32::from)
+}
+
+// The type is inferred from the tag which is present, so the value is Derived. ISOSpeedRatings
+// doesn't imply any type, so a value from it is Estimated
+fn probe_sensitivity(
+    exif: &Exif,
+    warnings: &mut Vec<Warning>,
+) -> Result<(u32, u16, Confidence), Error> {
+    for &(tag, field_name, sensitivity_type) in SENSITIVITY_PROBE_ORDER {
+        if exif.get_field(tag, In::PRIMARY).is_some() {
+            warnings.push(Warning::ProbedSensitivity(field_name.to_string()));
+            return Ok((
+                get_u32_field(exif, tag, field_name)?,
+                sensitivity_type,
+                Confidence::Derived,
+            ));
+        }
+    }
+    let sensitivity = get_legacy_sensitivity(exif)?;
+    warnings.push(Warning::ProbedSensitivity("ISOSpeedRatings".to_string()));
+    Ok((sensitivity, SENSITIVITY_TYPE_LEGACY, Confidence::Estimated))
+}
+
+// Returns the sensitivity, its SensitivityType, and how it was obtained
+fn get_sensitivity(
+    exif: &Exif,
+    warnings: &mut Vec<Warning>,
+) -> Result<(u32, u16, Confidence), Error> {
     if get_exif_version(exif)? < (2, 30) {
-        return Err(Error::Unsupported(
-            "Exif version < 2.3 is not supported".to_string(),
+        warnings.push(Warning::LegacySensitivity);
+        return Ok((
+            get_legacy_sensitivity(exif)?,
+            SENSITIVITY_TYPE_LEGACY,
+            Confidence::Estimated,
         ));
     }
+    if exif.get_field(Tag::SensitivityType, In::PRIMARY).is_none() {
+        return probe_sensitivity(exif, warnings);
+    }
     let sensitivity_type = get_u16_field(exif, Tag::SensitivityType, "SensitivityType")?;
     let sensitivity = match sensitivity_type {
         SENSITIVITY_TYPE_ISO => get_u32_field(exif, Tag::ISOSpeed, "ISOSpeed")?,
@@ -206,64 +524,1089 @@ fn get_sensitivity(exif: &Exif) -> Result<(u32, u16), Error> {
         SENSITIVITY_TYPE_SOS_AND_REI_AND_ISO => get_u32_field(exif, Tag::ISOSpeed, "ISOSpeed")?,
         _ => return Err(Error::Unsupported("Unknown SensitivityType".to_string())),
     };
-    Ok((sensitivity, sensitivity_type))
+    Ok((sensitivity, sensitivity_type, Confidence::Exact))
+}
+
+fn get_optional_u32_field(
+    exif: &Exif,
+    tag: Tag,
+    field_name: &'static str,
+) -> Result<Option<u32>, Error> {
+    exif.get_field(tag, In::PRIMARY)
+        .map(|_| get_u32_field(exif, tag, field_name))
+        .transpose()
 }
 
-fn get_exposure_time(exif: &Exif) -> Result<f32, Error> {
+fn get_sensitivities(
+    exif: &Exif,
+    makernote: Option<&IfdMakerNote>,
+) -> Result<Sensitivities, Error> {
+    Ok(Sensitivities {
+        standard_output_sensitivity: get_optional_u32_field(
+            exif,
+            Tag::StandardOutputSensitivity,
+            "StandardOutputSensitivity",
+        )?,
+        recommended_exposure_index: get_optional_u32_field(
+            exif,
+            Tag::RecommendedExposureIndex,
+            "RecommendedExposureIndex",
+        )?,
+        iso_speed: get_optional_u32_field(exif, Tag::ISOSpeed, "ISOSpeed")?,
+        iso_speed_latitude_yyy: get_optional_u32_field(
+            exif,
+            Tag::ISOSpeedLatitudeyyy,
+            "ISOSpeedLatitudeyyy",
+        )?,
+        iso_speed_latitude_zzz: get_optional_u32_field(
+            exif,
+            Tag::ISOSpeedLatitudezzz,
+            "ISOSpeedLatitudezzz",
+        )?,
+        extended: makernote.and_then(|x| x.extended_sensitivity()),
+    })
+}
+
+pub(in crate) fn get_exposure_time(exif: &Exif) -> Result<f32, Error> {
+    if exif.get_field(Tag::ExposureTime, In::PRIMARY).is_none() {
+        if let Some(shutter_speed) = get_shutter_speed(exif) {
+            return Ok(shutter_speed);
+        }
+    }
     get_rational_field(exif, Tag::ExposureTime, "ExposureTime").map(|x| x.to_f64() as f32)
 }
 
-fn get_temperature(exif: &Exif) -> Result<f32, Error> {
-    if !get_make(exif)?.eq("Canon") {
-        return Err(Error::Unsupported(
-            "Only Canon cameras are supported".to_string(),
-        ));
+// Cameras which only record the APEX shutter speed have no exact exposure time
+fn get_exposure_time_fraction(exif: &Exif) -> Option<Fraction> {
+    let value = get_rational_field(exif, Tag::ExposureTime, "ExposureTime").ok()?;
+    Fraction::new(value.num, value.denom)
+}
+
+fn get_capture_time(exif: &Exif) -> Result<Option<String>, Error> {
+    if exif.get_field(Tag::DateTimeOriginal, In::PRIMARY).is_none() {
+        return Ok(None);
+    }
+    let value = get_str_field(exif, Tag::DateTimeOriginal, "DateTimeOriginal")?;
+    let datetime = DateTime::from_ascii(value.as_bytes())?;
+    Ok(Some(format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        datetime.year,
+        datetime.month,
+        datetime.day,
+        datetime.hour,
+        datetime.minute,
+        datetime.second
+    )))
+}
+
+// Maker notes which are a regular IFD, unlike Canon's
+enum IfdMakerNote {
+    Nikon(Ifd),
+    Fujifilm(Ifd),
+    Olympus(OlympusMakerNote),
+    Panasonic(Ifd),
+    Pentax(PentaxMakerNote),
+}
+
+impl IfdMakerNote {
+    fn read(exif: &Exif, options: ParseOptions) -> Result<Option<IfdMakerNote>, Error> {
+        let make = get_make(exif)?;
+        if make.starts_with("NIKON") {
+            Ok(Some(IfdMakerNote::Nikon(parse_nikon_makernote(
+                get_makernote(exif)?,
+                options,
+            )?)))
+        } else if make == "FUJIFILM" {
+            Ok(Some(IfdMakerNote::Fujifilm(parse_fujifilm_makernote(
+                get_makernote(exif)?,
+                options,
+            )?)))
+        } else if make.starts_with("OLYMPUS") || make.starts_with("OM Digital") {
+            Ok(Some(IfdMakerNote::Olympus(parse_olympus_makernote(
+                get_makernote(exif)?,
+                options,
+            )?)))
+        } else if make == "Panasonic" {
+            let (makernote, offset) = get_makernote_with_offset(exif)?;
+            Ok(Some(IfdMakerNote::Panasonic(parse_panasonic_makernote(
+                makernote,
+                offset,
+                exif.buf(),
+                exif.little_endian(),
+                options,
+            )?)))
+        } else if make.starts_with("PENTAX") || make.starts_with("RICOH IMAGING") {
+            let (makernote, offset) = get_makernote_with_offset(exif)?;
+            Ok(Some(IfdMakerNote::Pentax(parse_pentax_makernote(
+                makernote,
+                offset,
+                exif.buf(),
+                exif.little_endian(),
+                options,
+            )?)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn vendor(&self) -> &'static str {
+        match self {
+            IfdMakerNote::Nikon(_) => "Nikon",
+            IfdMakerNote::Fujifilm(_) => "Fujifilm",
+            IfdMakerNote::Olympus(_) => "Olympus",
+            IfdMakerNote::Panasonic(_) => "Panasonic",
+            IfdMakerNote::Pentax(_) => "Pentax",
+        }
+    }
+
+    fn find(&self, tag: u16) -> Option<&Value> {
+        match self {
+            IfdMakerNote::Nikon(x) | IfdMakerNote::Fujifilm(x) | IfdMakerNote::Panasonic(x) => {
+                x.find(tag)
+            }
+            IfdMakerNote::Olympus(x) => find_entry(&x.main, tag),
+            IfdMakerNote::Pentax(x) => x.main.find(tag),
+        }
+    }
+
+    // Entries which were skipped in permissive mode
+    fn skipped(&self) -> &[SkippedEntry] {
+        match self {
+            IfdMakerNote::Nikon(x) | IfdMakerNote::Fujifilm(x) | IfdMakerNote::Panasonic(x) => {
+                &x.skipped
+            }
+            IfdMakerNote::Olympus(x) => &x.skipped,
+            IfdMakerNote::Pentax(x) => &x.main.skipped,
+        }
     }
 
-    let canon_makernote = parse_canon_makernote(&get_makernote(exif)?)?;
-    for entry in canon_makernote {
+    // Older cameras only record the serial number in the maker note
+    fn serial_number(&self) -> Option<String> {
+        let value = match self {
+            IfdMakerNote::Nikon(_) => self.find(TAG_NIKON_SERIAL_NUMBER),
+            IfdMakerNote::Fujifilm(_) => self.find(TAG_FUJIFILM_SERIAL_NUMBER),
+            IfdMakerNote::Olympus(x) => {
+                find_entry(&x.equipment, TAG_OLYMPUS_EQUIPMENT_SERIAL_NUMBER)
+            }
+            IfdMakerNote::Panasonic(_) => self.find(TAG_PANASONIC_INTERNAL_SERIAL_NUMBER),
+            IfdMakerNote::Pentax(_) => self.find(TAG_PENTAX_SERIAL_NUMBER),
+        };
+        match value {
+            Some(Value::Ascii(data)) => {
+                data.first().map(|x| String::from_utf8_lossy(x).to_string())
+            }
+            // Panasonic stores it as NUL padded undefined data
+            Some(Value::Undefined(data, _)) => Some(
+                String::from_utf8_lossy(data)
+                    .trim_end_matches('\0')
+                    .to_string(),
+            ),
+            _ => None,
+        }
+    }
+
+    fn shutter_count(&self) -> Option<u32> {
+        match self {
+            IfdMakerNote::Nikon(_) => match self.find(TAG_NIKON_SHUTTER_COUNT) {
+                Some(Value::Long(data)) => data.first().copied(),
+                _ => None,
+            },
+            IfdMakerNote::Fujifilm(_) => match self.find(TAG_FUJIFILM_IMAGE_COUNT) {
+                Some(Value::Short(data)) => data
+                    .first()
+                    .map(|x| u32::from(x & FUJIFILM_IMAGE_COUNT_MASK)),
+                _ => None,
+            },
+            IfdMakerNote::Olympus(_) | IfdMakerNote::Panasonic(_) | IfdMakerNote::Pentax(_) => None,
+        }
+    }
+
+    fn auto_sensitivity(&self) -> Option<bool> {
+        match self {
+            IfdMakerNote::Nikon(_) => match self.find(TAG_NIKON_ISO_SELECTION) {
+                Some(Value::Ascii(data)) => data.first().map(|x| {
+                    String::from_utf8_lossy(x)
+                        .trim()
+                        .eq_ignore_ascii_case("AUTO")
+                }),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn long_exposure_noise_reduction(&self) -> Option<bool> {
+        match self {
+            IfdMakerNote::Nikon(_) => match self.find(TAG_NIKON_NOISE_REDUCTION) {
+                Some(Value::Ascii(data)) => data.first().map(|x| {
+                    String::from_utf8_lossy(x)
+                        .trim()
+                        .eq_ignore_ascii_case("FPNR")
+                }),
+                _ => None,
+            },
+            IfdMakerNote::Olympus(x) => {
+                match find_entry(
+                    &x.camera_settings,
+                    TAG_OLYMPUS_CAMERA_SETTINGS_NOISE_REDUCTION,
+                )
+                .or_else(|| {
+                    find_entry(
+                        &x.image_processing,
+                        TAG_OLYMPUS_IMAGE_PROCESSING_NOISE_REDUCTION,
+                    )
+                }) {
+                    Some(Value::Short(data)) => {
+                        data.first().map(|x| x & OLYMPUS_NOISE_REDUCTION_FLAG != 0)
+                    }
+                    _ => None,
+                }
+            }
+            IfdMakerNote::Pentax(_) => match self.find(TAG_PENTAX_NOISE_REDUCTION) {
+                Some(Value::Short(data)) => data.first().map(|x| *x != 0),
+                _ => None,
+            },
+            IfdMakerNote::Fujifilm(_) | IfdMakerNote::Panasonic(_) => None,
+        }
+    }
+
+    // Canon has no such flag outside of its custom functions, and other vendors don't record one
+    fn extended_sensitivity(&self) -> Option<bool> {
+        match self {
+            IfdMakerNote::Nikon(_) => match self.find(TAG_NIKON_ISO_INFO) {
+                Some(Value::Undefined(data, _)) if data.len() >= NIKON_ISO_EXPANSION.end => {
+                    Some(data[NIKON_ISO_EXPANSION].iter().any(|x| *x != 0))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // Nikon only records the sensor temperature in the encrypted part of the maker note, and
+    // Fujifilm and Panasonic don't record it at all, so it's unknown. It's also unknown if the
+    // camera left out the tag, or wrote it with an unexpected type
+    fn temperature(&self) -> Option<Celsius> {
+        match self {
+            IfdMakerNote::Nikon(_) | IfdMakerNote::Fujifilm(_) | IfdMakerNote::Panasonic(_) => None,
+            IfdMakerNote::Olympus(x) => match find_entry(&x.main, TAG_OLYMPUS_SENSOR_TEMPERATURE) {
+                Some(Value::SShort(data)) if data.len() == 1 => Some(data[0]),
+                _ => match find_entry(&x.focus_info, TAG_OLYMPUS_FOCUS_INFO_SENSOR_TEMPERATURE) {
+                    Some(Value::SShort(data)) => data.first().copied(),
+                    _ => None,
+                },
+            }
+            .map(|x| Celsius(f32::from(x))),
+            IfdMakerNote::Pentax(_) => match self.find(TAG_PENTAX_CAMERA_TEMPERATURE) {
+                Some(Value::SShort(data)) if data.len() == 1 => Some(Celsius(f32::from(data[0]))),
+                Some(Value::SByte(data)) if data.len() == 1 => Some(Celsius(f32::from(data[0]))),
+                _ => None,
+            },
+        }
+    }
+
+    fn secondary_temperature(&self) -> Option<Celsius> {
+        match self {
+            IfdMakerNote::Pentax(x) => x
+                .temp_info
+                .get(PENTAX_TEMP_INFO_CAMERA_TEMPERATURE)
+                .map(|x| Celsius(f32::from(*x) / 10.0)),
+            _ => None,
+        }
+    }
+}
+
+fn find_entry(entries: &[IfdEntry], tag: u16) -> Option<&Value> {
+    entries.iter().find(|x| x.tag == tag).map(|x| &x.value)
+}
+
+fn check_canon(exif: &Exif) -> Result<(), Error> {
+    let make = get_make(exif)?;
+    if make != "Canon" {
+        return Err(Error::UnsupportedMake { make });
+    }
+    Ok(())
+}
+
+#[derive(Clone)]
+struct CanonMakerNote {
+    shotinfo: Vec<u16>,
+    // Only missing from some very old cameras
+    camera_settings: Option<Vec<u16>>,
+    // Older cameras store their custom functions in a per-model format, which isn't read
+    custom_functions: Option<Vec<u32>>,
+    // Entries which were skipped in permissive mode
+    skipped: Vec<SkippedEntry>,
+}
+
+impl CanonMakerNote {
+    fn auto_sensitivity(&self) -> Option<bool> {
+        let camera_iso = *self
+            .camera_settings
+            .as_ref()?
+            .get(CAMERA_SETTINGS_CAMERA_ISO)?;
+        match camera_iso {
+            CAMERA_ISO_AUTO | CAMERA_ISO_AUTO_HIGH => Some(true),
+            CAMERA_ISO_UNKNOWN => None,
+            CAMERA_ISO_MANUAL_MIN..=CAMERA_ISO_MANUAL_MAX => Some(false),
+            x if x & CAMERA_ISO_SPEED_FLAG != 0 => Some(false),
+            _ => None,
+        }
+    }
+
+    // Values of the given custom function, or None if it isn't recorded
+    fn custom_function(&self, id: u32) -> Option<&[u32]> {
+        let words = self.custom_functions.as_ref()?;
+        let groups = *words.get(1)?;
+        let mut position = CUSTOM_FUNCTIONS_HEADER_WORDS;
+        for _ in 0..groups {
+            let group_bytes = *words.get(position + 1)? as usize;
+            let count = *words.get(position + 2)?;
+            let group_end = position + 2 + group_bytes / 4;
+            position += CUSTOM_FUNCTIONS_GROUP_HEADER_WORDS;
+            for _ in 0..count {
+                let function = *words.get(position)?;
+                let values = *words.get(position + 1)? as usize;
+                let start = position + 2;
+                if function == id {
+                    return words.get(start..start + values);
+                }
+                position = start + values;
+            }
+            position = position.max(group_end);
+        }
+        None
+    }
+
+    fn long_exposure_noise_reduction(&self) -> Option<bool> {
+        match *self
+            .custom_function(CUSTOM_FUNCTION_LONG_EXPOSURE_NOISE_REDUCTION)?
+            .first()?
+        {
+            LONG_EXPOSURE_NOISE_REDUCTION_OFF => Some(false),
+            LONG_EXPOSURE_NOISE_REDUCTION_ON => Some(true),
+            // Including auto, where the camera decides for each frame, so whether it was applied
+            // isn't known
+            _ => None,
+        }
+    }
+}
+
+fn decode_canon_makernote(
+    makernote: &[u8],
+    little_endian: bool,
+    options: ParseOptions,
+) -> Result<CanonMakerNote, Error> {
+    let mut shotinfo = None;
+    let mut camera_settings = None;
+    let mut custom_functions = None;
+    let ifd = parse_canon_makernote(makernote, little_endian, options)?;
+    for entry in ifd.entries {
         if entry.tag == TAG_CANON_SHOTINFO {
             if let Value::Short(data) = entry.value {
-                return data
-                    .get(SHOTINFO_CAMERA_TEMPERATURE)
-                    .ok_or_else(|| {
-                        Error::InvalidData("Missing Camera Temperature field".to_string())
-                    })
-                    .map(|x| (*x - 128) as f32);
+                shotinfo = Some(data);
+            } else {
+                return Err(wrong_type("ShotInfo", "Short", &entry.value));
+            }
+        } else if entry.tag == TAG_CANON_CAMERA_SETTINGS {
+            if let Value::Short(data) = entry.value {
+                camera_settings = Some(data);
             } else {
-                return Err(Error::InvalidData(
-                    "ShotInfo field is not a short array".to_string(),
-                ));
+                return Err(wrong_type("CameraSettings", "Short", &entry.value));
+            }
+        } else if entry.tag == TAG_CANON_CUSTOM_FUNCTIONS {
+            if let Value::Long(data) = entry.value {
+                custom_functions = Some(data);
+            }
+        }
+    }
+
+    Ok(CanonMakerNote {
+        shotinfo: shotinfo.ok_or(Error::MissingField { field: "ShotInfo" })?,
+        camera_settings,
+        custom_functions,
+        skipped: ifd.skipped,
+    })
+}
+
+// Returns the contents of the ShotInfo entry of a Canon maker note
+pub(in crate) fn get_canon_shotinfo(exif: &Exif) -> Result<Vec<u16>, Error> {
+    check_canon(exif)?;
+    decode_canon_makernote(
+        get_makernote(exif)?,
+        exif.little_endian(),
+        ParseOptions::default(),
+    )
+    .map(|x| x.shotinfo)
+}
+
+// Consecutive frames of a burst usually have byte-identical maker notes, so the most recently
+// decoded maker note is kept and reused when the next frame's maker note matches
+#[derive(Default)]
+struct CanonMakerNoteCache {
+    last: Mutex<Option<(Vec<u8>, CanonMakerNote)>>,
+}
+
+impl CanonMakerNoteCache {
+    fn get(&self, exif: &Exif, options: ParseOptions) -> Result<CanonMakerNote, Error> {
+        check_canon(exif)?;
+        let makernote = get_makernote(exif)?;
+        let mut last = self.last.lock().unwrap();
+        if let Some((last_makernote, decoded)) = &*last {
+            if last_makernote.as_slice() == makernote {
+                trace!("Reusing decoded maker note from previous file");
+                return Ok(decoded.clone());
             }
         }
+        let decoded = decode_canon_makernote(makernote, exif.little_endian(), options)?;
+        *last = Some((makernote.to_vec(), decoded.clone()));
+        Ok(decoded)
     }
+}
+
+fn get_temperature(exif: &Exif, parser: &MetadataParser) -> Result<Celsius, Error> {
+    parser
+        .canon_makernote_cache
+        .get(exif, parser.options)?
+        .shotinfo
+        .get(SHOTINFO_CAMERA_TEMPERATURE)
+        .ok_or(Error::MissingField {
+            field: "CameraTemperature",
+        })
+        .map(|x| Celsius((i32::from(*x) - 128) as f32))
+}
 
-    Err(Error::InvalidData(
-        "Canon ShotInfo maker note not found".to_string(),
-    ))
+// Files are read in progressively longer prefixes, so that the EXIF parser, which reads TIFF based
+// raws to the end, never gets to the image data. This returns the length of the next prefix
+fn scan_length(length: u64, max_scan_bytes: Option<u64>) -> u64 {
+    max_scan_bytes.map_or(length, |x| length.min(x))
 }
 
-pub(in crate) struct MetadataParser {}
+fn scan_limit_reached(length: u64) -> Error {
+    Error::ScanLimitReached { limit: length }
+}
+
+// Calls parse with progressively longer prefixes of the file, until it succeeds or the whole file
+// has been read
+#[cfg(not(feature = "mmap"))]
+fn scan_file<T, F: Fn(&[u8]) -> Result<T, Error>>(
+    path: &Path,
+    max_scan_bytes: Option<u64>,
+    parse: F,
+) -> Result<T, Error> {
+    let mut file = File::open(path)?;
+    let mut data = vec![];
+    let mut length = scan_length(INITIAL_READ_BYTES, max_scan_bytes);
+    loop {
+        (&mut file)
+            .take(length - data.len() as u64)
+            .read_to_end(&mut data)?;
+        let complete = (data.len() as u64) < length;
+        match parse(&data) {
+            Ok(x) => return Ok(x),
+            Err(err) if complete => return Err(err),
+            Err(_) if Some(length) == max_scan_bytes => return Err(scan_limit_reached(length)),
+            Err(_) => length = scan_length(length * 4, max_scan_bytes),
+        }
+    }
+}
+
+// Only the pages of the file which are parsed get read, which matters for large raws on network
+// filesystems
+#[cfg(feature = "mmap")]
+fn scan_file<T, F: Fn(&[u8]) -> Result<T, Error>>(
+    path: &Path,
+    max_scan_bytes: Option<u64>,
+    parse: F,
+) -> Result<T, Error> {
+    let file = File::open(path)?;
+    // Safety: the map is only read, and dropped before returning. Another process truncating the
+    // file while it's being parsed would crash darkmagic, which never writes to the images it reads
+    let map = unsafe { memmap2::Mmap::map(&file)? };
+    let mut length = scan_length(INITIAL_READ_BYTES, max_scan_bytes);
+    loop {
+        let complete = length >= map.len() as u64;
+        match parse(&map[..(length as usize).min(map.len())]) {
+            Ok(x) => return Ok(x),
+            Err(err) if complete => return Err(err),
+            Err(_) if Some(length) == max_scan_bytes => return Err(scan_limit_reached(length)),
+            Err(_) => length = scan_length(length * 4, max_scan_bytes),
+        }
+    }
+}
+
+fn read_exif_from_file(path: &Path, max_scan_bytes: Option<u64>) -> Result<Exif, Error> {
+    scan_file(path, max_scan_bytes, |data| {
+        read_exif(&mut Cursor::new(data))
+    })
+}
+
+fn read_exif<R: BufRead + Seek>(reader: &mut R) -> Result<Exif, Error> {
+    let exifreader = exif::Reader::new();
+    if raf::is_raf(reader)? {
+        let jpeg = raf::read_embedded_jpeg(reader)?;
+        return Ok(exifreader.read_from_container(&mut Cursor::new(jpeg))?);
+    }
+    if heif::is_heif(reader)? {
+        return Ok(exifreader.read_raw(heif::read_exif_item(reader)?)?);
+    }
+    if canon_video::is_video(reader)? {
+        return canon_video::read_exif(reader);
+    }
+    if jxl::is_jxl(reader)? {
+        return Ok(exifreader.read_raw(jxl::read_exif_box(reader)?)?);
+    }
+    if vendor_tiff::is_vendor_tiff(reader)? {
+        return Ok(exifreader.read_raw(vendor_tiff::read_as_tiff(reader)?)?);
+    }
+    Ok(exifreader.read_from_container(reader)?)
+}
+
+// Fields from the XMP sidecar, if any, override those in the file itself
+fn confidence_of<T>(xmp_value: &Option<T>, image_confidence: Confidence) -> Confidence {
+    if xmp_value.is_some() {
+        Confidence::Overridden
+    } else {
+        image_confidence
+    }
+}
+
+fn build_metadata(
+    exif: &Exif,
+    xmp: XmpMetadata,
+    parser: &MetadataParser,
+) -> Result<ImageMetadata, Error> {
+    let mut confidence = FieldConfidence {
+        camera_model: confidence_of(&xmp.model, Confidence::Exact),
+        camera_serial_number: confidence_of(&xmp.serial_number, Confidence::Exact),
+        sensor_sensitivity: confidence_of(&xmp.sensitivity, Confidence::Exact),
+        exposure_time: confidence_of(
+            &xmp.exposure_time,
+            if exif.get_field(Tag::ExposureTime, In::PRIMARY).is_some() {
+                Confidence::Exact
+            } else {
+                Confidence::Derived
+            },
+        ),
+        temperature: confidence_of(&xmp.temperature, Confidence::Exact),
+        capture_time: confidence_of(&xmp.capture_time, Confidence::Exact),
+    };
+    let mut warnings = vec![];
+    if confidence.exposure_time == Confidence::Derived {
+        warnings.push(Warning::ExposureFromShutterSpeed);
+    }
+    let camera_make = match xmp.make {
+        Some(make) => make,
+        None => get_make(exif)?,
+    };
+    let camera_model = match xmp.model {
+        Some(model) => combine_make_and_model(camera_make.clone(), model),
+        None => get_model(exif)?,
+    };
+    let (sensor_sensitivity, sensitivity_type) = match xmp.sensitivity {
+        Some(sensitivity) => (
+            sensitivity,
+            xmp.sensitivity_type.unwrap_or_else(|| {
+                warnings.push(Warning::AssumedSensitivityType);
+                SENSITIVITY_TYPE_ISO
+            }),
+        ),
+        None => {
+            let (sensitivity, sensitivity_type, sensitivity_confidence) =
+                get_sensitivity(exif, &mut warnings)?;
+            confidence.sensor_sensitivity = sensitivity_confidence;
+            (sensitivity, sensitivity_type)
+        }
+    };
+    let (exposure_time, exposure_time_fraction) = match xmp.exposure_time {
+        Some(x) => (x, xmp.exposure_time_fraction),
+        None => (
+            Seconds(get_exposure_time(exif)?),
+            get_exposure_time_fraction(exif),
+        ),
+    };
+    let ifd_makernote = IfdMakerNote::read(exif, parser.options)?;
+    // The camera's values would contradict a sensitivity from the sidecar
+    let sensitivities = match xmp.sensitivity {
+        Some(_) => Sensitivities::default(),
+        None => get_sensitivities(exif, ifd_makernote.as_ref())?,
+    };
+    let camera_serial_number = match xmp.serial_number {
+        Some(x) => x,
+        None => match get_serial_number(exif) {
+            Ok(x) => x,
+            Err(err) => {
+                let serial_number = ifd_makernote
+                    .as_ref()
+                    .and_then(|x| x.serial_number())
+                    .ok_or(err)?;
+                warnings.push(Warning::SerialFromMakerNote);
+                confidence.camera_serial_number = Confidence::Derived;
+                serial_number
+            }
+        },
+    };
+    let temperature = match xmp.temperature {
+        Some(x) => Some(x),
+        None => match &ifd_makernote {
+            Some(makernote) => {
+                let temperature = makernote.temperature();
+                if temperature.is_none() {
+                    warnings.push(Warning::TemperatureNotRecorded(
+                        makernote.vendor().to_string(),
+                    ));
+                }
+                temperature
+            }
+            None => Some(get_temperature(exif, parser)?),
+        },
+    };
+    if let Some(x) = temperature {
+        if x.0 < PLAUSIBLE_TEMPERATURE_MIN || x.0 > PLAUSIBLE_TEMPERATURE_MAX {
+            warnings.push(Warning::ImplausibleTemperature(x));
+        }
+    }
+    // Optional, so a missing or unreadable Canon maker note is not an error here
+    let canon_makernote = match &ifd_makernote {
+        Some(_) => None,
+        None => parser.canon_makernote_cache.get(exif, parser.options).ok(),
+    };
+    let skipped = match (&ifd_makernote, &canon_makernote) {
+        (Some(makernote), _) => makernote.skipped(),
+        (None, Some(makernote)) => &makernote.skipped,
+        (None, None) => &[],
+    };
+    for entry in skipped {
+        warnings.push(Warning::SkippedIfdEntry {
+            tag: entry.tag,
+            reason: entry.reason.clone(),
+        });
+    }
+    let auto_sensitivity = match &ifd_makernote {
+        Some(makernote) => makernote.auto_sensitivity(),
+        None => canon_makernote.as_ref().and_then(|x| x.auto_sensitivity()),
+    };
+    let long_exposure_noise_reduction = match &ifd_makernote {
+        Some(makernote) => makernote.long_exposure_noise_reduction(),
+        None => canon_makernote
+            .as_ref()
+            .and_then(|x| x.long_exposure_noise_reduction()),
+    };
+    Ok(ImageMetadata {
+        camera_make,
+        camera_model,
+        camera_serial_number,
+        sensor_sensitivity,
+        sensitivity_type,
+        sensitivities,
+        exposure_time,
+        exposure_time_fraction,
+        temperature,
+        capture_time: match xmp.capture_time {
+            Some(x) => Some(x),
+            None => get_capture_time(exif)?,
+        },
+        secondary_temperature: ifd_makernote
+            .as_ref()
+            .and_then(|x| x.secondary_temperature()),
+        shutter_count: ifd_makernote.and_then(|x| x.shutter_count()),
+        auto_sensitivity,
+        long_exposure_noise_reduction,
+        thumbnail: get_thumbnail(exif),
+        confidence,
+        warnings,
+    })
+}
+
+fn log_warnings(path: &Path, metadata: &ImageMetadata) {
+    for warning in metadata.warnings.iter() {
+        log!(warning.level(), "{}: {}", path.display(), warning);
+    }
+}
+
+pub(in crate) struct MetadataParser {
+    canon_makernote_cache: CanonMakerNoteCache,
+    options: ParseOptions,
+    // Files are only read this far looking for the metadata
+    max_scan_bytes: Option<u64>,
+    cache: Option<MetadataCache>,
+}
 
 impl MetadataParser {
-    pub fn new() -> MetadataParser {
-        MetadataParser {}
+    pub fn new(options: ParseOptions) -> MetadataParser {
+        MetadataParser {
+            canon_makernote_cache: CanonMakerNoteCache::default(),
+            options,
+            max_scan_bytes: None,
+            cache: None,
+        }
+    }
+
+    pub fn with_max_scan_bytes(self, max_scan_bytes: Option<u64>) -> MetadataParser {
+        MetadataParser {
+            max_scan_bytes,
+            ..self
+        }
+    }
+
+    pub fn with_cache(self, cache: Option<MetadataCache>) -> MetadataParser {
+        MetadataParser { cache, ..self }
     }
 
     pub fn read_file<P: AsRef<Path>>(&self, path: P) -> Result<ImageMetadata, Error> {
-        let file = std::fs::File::open(path)?;
-        let mut bufreader = std::io::BufReader::new(&file);
-        let exifreader = exif::Reader::new();
-        let exif = exifreader.read_from_container(&mut bufreader)?;
-
-        let (sensor_sensitivity, sensitivity_type) = get_sensitivity(&exif)?;
-        Ok(ImageMetadata {
-            camera_model: get_model(&exif)?,
-            camera_serial_number: get_serial_number(&exif)?,
-            sensor_sensitivity,
-            sensitivity_type,
-            exposure_time: get_exposure_time(&exif)?,
-            temperature: get_temperature(&exif)?,
+        let slot = self
+            .cache
+            .as_ref()
+            .map(|x| x.slot(path.as_ref()))
+            .transpose()?;
+        if let Some(metadata) = slot.as_ref().and_then(|x| x.get()) {
+            log_warnings(path.as_ref(), &metadata);
+            return Ok(metadata);
+        }
+        let exif = read_exif_from_file(path.as_ref(), self.max_scan_bytes)?;
+        let xmp = read_sidecar(path.as_ref())?.unwrap_or_default();
+        let metadata = build_metadata(&exif, xmp, self)?;
+        log_warnings(path.as_ref(), &metadata);
+        if let Some(slot) = slot {
+            slot.put(&metadata);
+        }
+        Ok(metadata)
+    }
+
+    // Reads an image which is not on disk, such as one being streamed. No sidecar is consulted
+    pub fn read_from_reader<R: BufRead + Seek>(
+        &self,
+        reader: &mut R,
+    ) -> Result<ImageMetadata, Error> {
+        build_metadata(&read_exif(reader)?, XmpMetadata::default(), self)
+    }
+
+    pub fn read_bytes(&self, data: &[u8]) -> Result<ImageMetadata, Error> {
+        self.read_from_reader(&mut Cursor::new(data))
+    }
+
+    // Cross checks the exposure parameters against the other places they're recorded. Disagreement
+    // usually means the EXIF data was modified by third-party software
+    pub fn audit_file<P: AsRef<Path>>(&self, path: P) -> Result<Vec<Mismatch>, Error> {
+        audit_exposure(&read_exif_from_file(path.as_ref(), self.max_scan_bytes)?)
+    }
+
+    // Returns the largest embedded JPEG preview or thumbnail. Previews are usually stored after the
+    // metadata, so the file is scanned until the preview is complete, rather than until the
+    // metadata can be parsed
+    pub fn read_preview<P: AsRef<Path>>(&self, path: P) -> Result<Vec<u8>, Error> {
+        scan_file(path.as_ref(), self.max_scan_bytes, |data| {
+            let mut reader = Cursor::new(data);
+            if raf::is_raf(&mut reader)? {
+                return Ok(raf::read_embedded_jpeg(&mut reader)?);
+            }
+            find_preview(&read_exif(&mut reader)?)
+                .map(|x| x.to_vec())
+                .ok_or(Error::MissingField {
+                    field: "JPEG preview",
+                })
         })
     }
+
+    pub fn audit_bytes(&self, data: &[u8]) -> Result<Vec<Mismatch>, Error> {
+        audit_exposure(&read_exif(&mut Cursor::new(data))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tiff::ParseMode;
+    use exif::experimental::Writer;
+    use exif::Field;
+
+    // Pentax maker note, whose pointers are relative to its own start, holding a temperature of
+    // 20 C and optionally a serial number of up to 3 characters
+    fn pentax_makernote(serial_number: Option<&[u8]>) -> Vec<u8> {
+        let mut entries: Vec<(u16, u16, u32, [u8; 4])> =
+            vec![(TAG_PENTAX_CAMERA_TEMPERATURE, 6, 1, [20, 0, 0, 0])];
+        if let Some(serial_number) = serial_number {
+            let mut value = [0; 4];
+            value[..serial_number.len()].copy_from_slice(serial_number);
+            entries.push((TAG_PENTAX_SERIAL_NUMBER, 2, 4, value));
+        }
+        let mut data = b"PENTAX \0MM".to_vec();
+        data.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+        for (tag, value_type, count, value) in entries {
+            data.extend_from_slice(&tag.to_be_bytes());
+            data.extend_from_slice(&value_type.to_be_bytes());
+            data.extend_from_slice(&count.to_be_bytes());
+            data.extend_from_slice(&value);
+        }
+        data.extend_from_slice(&[0; 4]);
+        data
+    }
+
+    // Olympus maker note with a FocusInfo sub-IFD, which optionally holds a sensor temperature
+    fn olympus_makernote(temperature: Option<i16>) -> Vec<u8> {
+        let mut data = b"OLYMPUS\0MM\x03\x00".to_vec();
+        let focus_info_offset = data.len() as u32 + 18;
+        data.extend_from_slice(&1u16.to_be_bytes());
+        data.extend_from_slice(&0x2050u16.to_be_bytes());
+        data.extend_from_slice(&13u16.to_be_bytes());
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(&focus_info_offset.to_be_bytes());
+        data.extend_from_slice(&[0; 4]);
+        match temperature {
+            Some(temperature) => {
+                data.extend_from_slice(&1u16.to_be_bytes());
+                data.extend_from_slice(&TAG_OLYMPUS_FOCUS_INFO_SENSOR_TEMPERATURE.to_be_bytes());
+                data.extend_from_slice(&8u16.to_be_bytes());
+                data.extend_from_slice(&2u32.to_be_bytes());
+                data.extend_from_slice(&temperature.to_be_bytes());
+                data.extend_from_slice(&[0; 2]);
+            }
+            None => data.extend_from_slice(&0u16.to_be_bytes()),
+        }
+        data.extend_from_slice(&[0; 4]);
+        data
+    }
+
+    fn ascii(value: &str) -> Value {
+        Value::Ascii(vec![value.as_bytes().to_vec()])
+    }
+
+    fn read_fields(fields: Vec<(Tag, Value)>) -> ImageMetadata {
+        read_fields_with_options(fields, ParseOptions::default()).unwrap()
+    }
+
+    fn read_fields_with_options(
+        fields: Vec<(Tag, Value)>,
+        options: ParseOptions,
+    ) -> Result<ImageMetadata, Error> {
+        let fields: Vec<Field> = fields
+            .into_iter()
+            .map(|(tag, value)| Field {
+                tag,
+                ifd_num: In::PRIMARY,
+                value,
+            })
+            .collect();
+        let mut writer = Writer::new();
+        for field in fields.iter() {
+            writer.push_field(field);
+        }
+        let mut data = Cursor::new(vec![]);
+        writer.write(&mut data, false).unwrap();
+        let exif = exif::Reader::new().read_raw(data.into_inner()).unwrap();
+        let parser = MetadataParser::new(options);
+        build_metadata(&exif, XmpMetadata::default(), &parser)
+    }
+
+    fn pentax_fields(exif_version: &[u8], serial_in_makernote: bool) -> Vec<(Tag, Value)> {
+        let mut fields = vec![
+            (Tag::Make, ascii("PENTAX")),
+            (Tag::Model, ascii("PENTAX K-1")),
+            (
+                Tag::ExposureTime,
+                Value::Rational(vec![Rational { num: 1, denom: 100 }]),
+            ),
+            (Tag::ExifVersion, Value::Undefined(exif_version.to_vec(), 0)),
+            (Tag::PhotographicSensitivity, Value::Short(vec![800])),
+        ];
+        if serial_in_makernote {
+            fields.push((
+                Tag::MakerNote,
+                Value::Undefined(pentax_makernote(Some(b"123")), 0),
+            ));
+        } else {
+            fields.push((Tag::MakerNote, Value::Undefined(pentax_makernote(None), 0)));
+            fields.push((Tag::BodySerialNumber, ascii("123")));
+        }
+        fields
+    }
+
+    // Every warning about a fallback has to be matched by a confidence other than Exact
+    fn assert_fallbacks_not_exact(metadata: &ImageMetadata) {
+        for warning in metadata.warnings.iter() {
+            let confidence = match warning {
+                Warning::LegacySensitivity | Warning::ProbedSensitivity(_) => {
+                    metadata.confidence.sensor_sensitivity
+                }
+                Warning::SerialFromMakerNote => metadata.confidence.camera_serial_number,
+                Warning::ExposureFromShutterSpeed => metadata.confidence.exposure_time,
+                _ => continue,
+            };
+            assert_ne!(confidence, Confidence::Exact, "{}", warning);
+        }
+    }
+
+    #[test]
+    fn sensitivity_type_is_exact() {
+        let mut fields = pentax_fields(b"0230", false);
+        fields.push((
+            Tag::SensitivityType,
+            Value::Short(vec![SENSITIVITY_TYPE_ISO]),
+        ));
+        fields.push((Tag::ISOSpeed, Value::Long(vec![800])));
+        let metadata = read_fields(fields);
+        assert_eq!(metadata.sensor_sensitivity, 800);
+        assert_eq!(metadata.confidence.sensor_sensitivity, Confidence::Exact);
+        assert_eq!(metadata.confidence.camera_serial_number, Confidence::Exact);
+        assert!(metadata.warnings.is_empty());
+    }
+
+    #[test]
+    fn legacy_sensitivity_is_estimated() {
+        let metadata = read_fields(pentax_fields(b"0221", false));
+        assert_eq!(
+            metadata.confidence.sensor_sensitivity,
+            Confidence::Estimated
+        );
+        assert_fallbacks_not_exact(&metadata);
+    }
+
+    #[test]
+    fn probed_sensitivity_is_not_exact() {
+        let metadata = read_fields(pentax_fields(b"0230", false));
+        assert_eq!(
+            metadata.confidence.sensor_sensitivity,
+            Confidence::Estimated
+        );
+        assert_fallbacks_not_exact(&metadata);
+
+        let mut fields = pentax_fields(b"0230", false);
+        fields.push((Tag::ISOSpeed, Value::Long(vec![800])));
+        let metadata = read_fields(fields);
+        assert_eq!(metadata.confidence.sensor_sensitivity, Confidence::Derived);
+        assert_fallbacks_not_exact(&metadata);
+    }
+
+    #[test]
+    fn serial_number_from_makernote_is_derived() {
+        let metadata = read_fields(pentax_fields(b"0230", true));
+        assert_eq!(metadata.camera_serial_number, "123");
+        assert_eq!(
+            metadata.confidence.camera_serial_number,
+            Confidence::Derived
+        );
+        assert_fallbacks_not_exact(&metadata);
+    }
+
+    #[test]
+    fn skipped_entries_are_warned() {
+        // Append an entry of an unknown type to the maker note
+        let mut makernote = pentax_makernote(None);
+        makernote.truncate(makernote.len() - 4);
+        makernote[11] += 1;
+        makernote.extend_from_slice(&[0x00, 0x48, 0x00, 0x63, 0, 0, 0, 1, 0, 0, 0, 0]);
+        makernote.extend_from_slice(&[0; 4]);
+        let fields = with_makernote(pentax_fields(b"0230", false), makernote);
+
+        let err = read_fields_with_options(fields.clone(), ParseOptions::default())
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::CorruptIfd { .. }), "{}", err);
+
+        let options = ParseOptions {
+            mode: ParseMode::Permissive,
+            ..ParseOptions::default()
+        };
+        let metadata = read_fields_with_options(fields, options).unwrap();
+        assert_eq!(metadata.temperature, Some(Celsius(20.0)));
+        assert!(metadata.warnings.iter().any(|x| matches!(
+            x,
+            Warning::SkippedIfdEntry {
+                tag: Some(0x48),
+                ..
+            }
+        )));
+    }
+
+    fn with_makernote(mut fields: Vec<(Tag, Value)>, makernote: Vec<u8>) -> Vec<(Tag, Value)> {
+        for (tag, value) in fields.iter_mut() {
+            if *tag == Tag::MakerNote {
+                *value = Value::Undefined(makernote.clone(), 0);
+            }
+        }
+        fields
+    }
+
+    #[test]
+    fn pentax_temperatures() {
+        // Append a TempInfo entry, pointing to a block after the IFD
+        let mut makernote = pentax_makernote(None);
+        makernote.truncate(makernote.len() - 4);
+        makernote[11] += 1;
+        let temp_info_offset = makernote.len() as u32 + 12 + 4;
+        makernote.extend_from_slice(&[0x03, 0xff, 0x00, 0x07, 0, 0, 0, 32]);
+        makernote.extend_from_slice(&temp_info_offset.to_be_bytes());
+        makernote.extend_from_slice(&[0; 4]);
+        let mut temp_info = [0; 32];
+        temp_info[24..26].copy_from_slice(&253i16.to_be_bytes());
+        makernote.extend_from_slice(&temp_info);
+        let metadata = read_fields(with_makernote(pentax_fields(b"0230", false), makernote));
+        assert_eq!(metadata.temperature, Some(Celsius(20.0)));
+        assert_eq!(metadata.secondary_temperature, Some(Celsius(25.3)));
+
+        // The tag of the camera temperature is changed, so it's missing
+        let mut makernote = pentax_makernote(None);
+        makernote[13] -= 1;
+        let metadata = read_fields(with_makernote(pentax_fields(b"0230", false), makernote));
+        assert_eq!(metadata.temperature, None);
+        assert_eq!(metadata.secondary_temperature, None);
+        assert!(metadata
+            .warnings
+            .iter()
+            .any(|x| matches!(x, Warning::TemperatureNotRecorded(_))));
+    }
+
+    #[test]
+    fn olympus_temperature_from_focus_info() {
+        let fields = |temperature| {
+            vec![
+                (Tag::Make, ascii("OM Digital Solutions")),
+                (Tag::Model, ascii("OM-1")),
+                (
+                    Tag::ExposureTime,
+                    Value::Rational(vec![Rational { num: 1, denom: 100 }]),
+                ),
+                (Tag::ExifVersion, Value::Undefined(b"0231".to_vec(), 0)),
+                (Tag::PhotographicSensitivity, Value::Short(vec![800])),
+                (Tag::BodySerialNumber, ascii("123")),
+                (
+                    Tag::MakerNote,
+                    Value::Undefined(olympus_makernote(temperature), 0),
+                ),
+            ]
+        };
+
+        let metadata = read_fields(fields(Some(31)));
+        assert_eq!(metadata.temperature, Some(Celsius(31.0)));
+        assert!(metadata
+            .warnings
+            .iter()
+            .all(|x| !matches!(x, Warning::TemperatureNotRecorded(_))));
+
+        // Missing from both the main IFD and FocusInfo
+        let metadata = read_fields(fields(None));
+        assert_eq!(metadata.temperature, None);
+        assert!(metadata
+            .warnings
+            .iter()
+            .any(|x| matches!(x, Warning::TemperatureNotRecorded(_))));
+    }
+
+    #[test]
+    fn generated_sidecar_round_trips() {
+        let mut fields = pentax_fields(b"0230", false);
+        fields.push((
+            Tag::SensitivityType,
+            Value::Short(vec![SENSITIVITY_TYPE_SOS]),
+        ));
+        fields.push((Tag::StandardOutputSensitivity, Value::Long(vec![800])));
+        let metadata = read_fields(fields);
+        let xmp = crate::xmp::parse_xmp(&crate::xmp::format_xmp(&metadata)).unwrap();
+        assert!(xmp.generated);
+        assert_eq!(xmp.make.as_deref(), Some("PENTAX"));
+        assert_eq!(xmp.model.as_deref(), Some("PENTAX K-1"));
+        assert_eq!(xmp.sensitivity, Some(800));
+        assert_eq!(xmp.sensitivity_type, Some(SENSITIVITY_TYPE_SOS));
+        assert_eq!(xmp.temperature, Some(Celsius(20.0)));
+    }
 }
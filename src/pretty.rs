@@ -0,0 +1,119 @@
+use crate::metadata::{Confidence, ImageMetadata};
+use crate::units::{round_tenths, TemperatureUnit};
+use std::fmt::Write;
+
+// Values which weren't read directly from the image are marked
+fn with_confidence(value: String, confidence: Confidence) -> String {
+    match confidence {
+        Confidence::Exact => value,
+        Confidence::Derived => format!("{} (derived)", value),
+        Confidence::Estimated => format!("{} (estimated)", value),
+        Confidence::Overridden => format!("{} (from sidecar)", value),
+    }
+}
+
+// Key-value listing with units, for reading in a terminal
+pub(in crate) fn format_metadata(metadata: &ImageMetadata, unit: TemperatureUnit) -> String {
+    let confidence = &metadata.confidence;
+    let mut fields = vec![
+        (
+            "Camera",
+            with_confidence(metadata.camera_model.clone(), confidence.camera_model),
+        ),
+        (
+            "Serial number",
+            with_confidence(
+                metadata.camera_serial_number.clone(),
+                confidence.camera_serial_number,
+            ),
+        ),
+        (
+            "Sensitivity",
+            with_confidence(
+                format!(
+                    "{} {}{}",
+                    metadata.sensitivity_name(),
+                    metadata.sensor_sensitivity,
+                    if metadata.sensitivities.extended == Some(true) {
+                        " (extended)"
+                    } else {
+                        ""
+                    }
+                ),
+                confidence.sensor_sensitivity,
+            ),
+        ),
+        (
+            "Exposure time",
+            with_confidence(
+                match metadata.exposure_time_fraction {
+                    Some(x) if x.numerator == 1 => format!("{} s", x),
+                    _ => format!("{} s", metadata.exposure_time.shutter_speed()),
+                },
+                confidence.exposure_time,
+            ),
+        ),
+        (
+            "Temperature",
+            with_confidence(
+                match metadata.temperature {
+                    Some(x) => format!("{} {}", round_tenths(unit.convert(x)), unit),
+                    None => "unknown".to_string(),
+                },
+                confidence.temperature,
+            ),
+        ),
+    ];
+    if let Some(x) = metadata.secondary_temperature {
+        fields.push((
+            "Temperature 2",
+            format!("{} {}", round_tenths(unit.convert(x)), unit),
+        ));
+    }
+    // Only worth listing when they disagree with, or add to, the sensitivity above
+    let sensitivities = metadata.sensitivities.values();
+    if sensitivities.len() > 1 {
+        let values: Vec<String> = sensitivities
+            .iter()
+            .map(|(name, value)| format!("{} {}", name, value))
+            .collect();
+        fields.push(("All sensitivities", values.join(", ")));
+    }
+    if let (Some(yyy), Some(zzz)) = (
+        metadata.sensitivities.iso_speed_latitude_yyy,
+        metadata.sensitivities.iso_speed_latitude_zzz,
+    ) {
+        fields.push(("ISO latitude", format!("{}-{}", yyy, zzz)));
+    }
+    if let Some(auto_sensitivity) = metadata.auto_sensitivity {
+        fields.push((
+            "Auto ISO",
+            if auto_sensitivity { "yes" } else { "no" }.to_string(),
+        ));
+    }
+    if let Some(lenr) = metadata.long_exposure_noise_reduction {
+        fields.push((
+            "Long exposure NR",
+            if lenr { "on" } else { "off" }.to_string(),
+        ));
+    }
+    if let Some(capture_time) = &metadata.capture_time {
+        fields.push((
+            "Capture time",
+            with_confidence(capture_time.replace('T', " "), confidence.capture_time),
+        ));
+    }
+    if let Some(shutter_count) = metadata.shutter_count {
+        fields.push(("Shutter count", shutter_count.to_string()));
+    }
+    for warning in metadata.warnings.iter() {
+        fields.push(("Warning", warning.to_string()));
+    }
+
+    let width = fields.iter().map(|(name, _)| name.len()).max().unwrap();
+    let mut output = String::new();
+    for (name, value) in fields {
+        writeln!(output, "{:<width$}  {}", name, value, width = width + 1).unwrap();
+    }
+    output
+}
@@ -0,0 +1,72 @@
+use exif::{Exif, In, Tag};
+use serde::{Deserialize, Serialize};
+
+const JPEG_START_OF_IMAGE: &[u8] = &[0xff, 0xd8];
+// As defined for TIFF tag 0x103
+const COMPRESSION_JPEG: u16 = 6;
+
+// Location of the thumbnail image described by IFD1
+#[derive(Debug, Serialize, Deserialize)]
+pub(in crate) struct Thumbnail {
+    // Offset from the start of the TIFF header
+    pub offset: u32,
+    pub length: u32,
+    // As defined for TIFF tag 0x103. 6 is JPEG, and 1 is uncompressed
+    pub compression: u16,
+    // The thumbnail extends past the end of the data, which usually means the file is truncated
+    pub truncated: bool,
+}
+
+// JPEG thumbnails are referenced by JPEGInterchangeFormat, and uncompressed ones by a single strip
+pub(in crate) fn get_thumbnail(exif: &Exif) -> Option<Thumbnail> {
+    let get = |tag| {
+        exif.get_field(tag, In::THUMBNAIL)
+            .and_then(|x| x.value.get_uint(0))
+    };
+    let (offset, length) = match (
+        get(Tag::JPEGInterchangeFormat),
+        get(Tag::JPEGInterchangeFormatLength),
+    ) {
+        (Some(offset), Some(length)) => (offset, length),
+        _ => (get(Tag::StripOffsets)?, get(Tag::StripByteCounts)?),
+    };
+    let compression = get(Tag::Compression)
+        .map(|x| x as u16)
+        .unwrap_or(COMPRESSION_JPEG);
+    Some(Thumbnail {
+        offset,
+        length,
+        compression,
+        truncated: u64::from(offset) + u64::from(length) > exif.buf().len() as u64,
+    })
+}
+
+// Returns the largest JPEG preview in the TIFF data. Raws usually have a large preview in the
+// strips of IFD0, and a small thumbnail in IFD1
+pub(in crate) fn find_preview(exif: &Exif) -> Option<&[u8]> {
+    let primary = exif
+        .get_field(Tag::StripOffsets, In::PRIMARY)
+        .and_then(|x| x.value.get_uint(0))
+        .zip(
+            exif.get_field(Tag::StripByteCounts, In::PRIMARY)
+                .and_then(|x| x.value.get_uint(0)),
+        );
+    let thumbnail = get_thumbnail(exif)
+        .filter(|x| x.compression == COMPRESSION_JPEG)
+        .map(|x| (x.offset, x.length));
+    vec![primary, thumbnail]
+        .into_iter()
+        .flatten()
+        .filter_map(|(offset, length)| {
+            let offset = offset as usize;
+            let data = exif
+                .buf()
+                .get(offset..offset.checked_add(length as usize)?)?;
+            if data.starts_with(JPEG_START_OF_IMAGE) {
+                Some(data)
+            } else {
+                None
+            }
+        })
+        .max_by_key(|x| x.len())
+}
@@ -0,0 +1,147 @@
+use crate::error::Error;
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+// Width of the bar, in characters
+const BAR_WIDTH: usize = 30;
+// The bar is redrawn at most this often, so that fast batches aren't slowed down by the terminal
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, PartialEq)]
+pub(in crate) enum ProgressFormat {
+    // One JSON object per line, so that programs wrapping the CLI don't have to parse the human
+    // readable output
+    Json,
+    // A single line with the throughput and estimated time remaining, for interactive use
+    Bar,
+}
+
+// Progress of a batch operation, written to stderr
+pub(in crate) struct Progress {
+    command: &'static str,
+    total: usize,
+    completed: usize,
+    failed: usize,
+    format: Option<ProgressFormat>,
+    start: Instant,
+    last_draw: Option<Instant>,
+}
+
+fn format_duration(seconds: u64) -> String {
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds % 3600 / 60,
+            seconds % 60
+        )
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+impl Progress {
+    pub fn new(command: &'static str, total: usize, format: Option<ProgressFormat>) -> Progress {
+        let progress = Progress {
+            command,
+            total,
+            completed: 0,
+            failed: 0,
+            format,
+            start: Instant::now(),
+            last_draw: None,
+        };
+        progress.emit(json!({"event": "start", "command": command, "total": total}));
+        progress
+    }
+
+    // Callers print the error, if any, after this returns, so the bar is cleared to leave the line
+    // free for it
+    pub fn file_done(&mut self, path: &str, error: Option<&Error>) {
+        self.completed += 1;
+        if error.is_some() {
+            self.failed += 1;
+        }
+        match self.format {
+            Some(ProgressFormat::Json) => {
+                let mut event = json!({
+                    "event": "file",
+                    "command": self.command,
+                    "path": path,
+                    "status": "ok",
+                    "completed": self.completed,
+                    "total": self.total,
+                });
+                if let Some(error) = error {
+                    event["status"] = json!("error");
+                    event["error"] = json!(error.to_string());
+                    event["code"] = json!(error.code());
+                }
+                self.emit(event);
+            }
+            Some(ProgressFormat::Bar) if error.is_some() => clear_bar(),
+            Some(ProgressFormat::Bar) => self.draw(),
+            None => {}
+        }
+    }
+
+    pub fn finish(&self) {
+        match self.format {
+            Some(ProgressFormat::Json) => self.emit(json!({
+                "event": "finish",
+                "command": self.command,
+                "completed": self.completed,
+                "failed": self.failed,
+            })),
+            Some(ProgressFormat::Bar) => clear_bar(),
+            None => {}
+        }
+    }
+
+    fn emit(&self, event: serde_json::Value) {
+        if self.format == Some(ProgressFormat::Json) {
+            eprintln!("{}", event);
+        }
+    }
+
+    fn draw(&mut self) {
+        let now = Instant::now();
+        if self.completed < self.total
+            && matches!(self.last_draw, Some(x) if now.duration_since(x) < REDRAW_INTERVAL)
+        {
+            return;
+        }
+        self.last_draw = Some(now);
+
+        let elapsed = now.duration_since(self.start).as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            self.completed as f64 / elapsed
+        } else {
+            0.0
+        };
+        let remaining = self.total.saturating_sub(self.completed);
+        let eta = if rate > 0.0 {
+            format_duration((remaining as f64 / rate).round() as u64)
+        } else {
+            "?".to_string()
+        };
+        let filled = match self.total {
+            0 => BAR_WIDTH,
+            total => BAR_WIDTH * self.completed.min(total) / total,
+        };
+        eprint!(
+            "\r{} [{}{}] {}/{} {:.1} files/s ETA {}\x1b[K",
+            self.command,
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            self.completed,
+            self.total,
+            rate,
+            eta
+        );
+    }
+}
+
+fn clear_bar() {
+    eprint!("\r\x1b[K");
+}
@@ -0,0 +1,39 @@
+use byteorder::{BigEndian, ReadBytesExt};
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+const RAF_MAGIC: &[u8] = b"FUJIFILMCCD-RAW ";
+// Location of the embedded JPEG's offset and length, both big endian u32s
+const RAF_JPEG_POINTER_OFFSET: u64 = 84;
+// The EXIF data is in the preview JPEG, which is never this large
+const MAX_JPEG_BYTES: u32 = 64 * 1024 * 1024;
+
+// Checks for the RAF header, and rewinds the reader to the start of the file
+pub(in crate) fn is_raf<R: Read + Seek>(reader: &mut R) -> io::Result<bool> {
+    let mut magic = [0u8; RAF_MAGIC.len()];
+    let result = reader.read_exact(&mut magic);
+    reader.seek(SeekFrom::Start(0))?;
+    match result {
+        Ok(()) => Ok(magic == RAF_MAGIC),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+// RAF files start with a proprietary header which points to a JPEG preview, and that JPEG holds
+// the EXIF data
+pub(in crate) fn read_embedded_jpeg<R: Read + Seek>(reader: &mut R) -> io::Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(RAF_JPEG_POINTER_OFFSET))?;
+    let jpeg_offset = reader.read_u32::<BigEndian>()?;
+    let jpeg_length = reader.read_u32::<BigEndian>()?;
+    if jpeg_length > MAX_JPEG_BYTES {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "RAF preview JPEG is implausibly large",
+        ));
+    }
+    reader.seek(SeekFrom::Start(u64::from(jpeg_offset)))?;
+    let mut jpeg = vec![0u8; jpeg_length as usize];
+    reader.read_exact(&mut jpeg)?;
+    Ok(jpeg)
+}
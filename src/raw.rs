@@ -0,0 +1,96 @@
+use crate::error::Error;
+use rawloader::{RawImage, RawImageData};
+use std::ops::Range;
+use std::path::Path;
+
+// Names of the colors returned by rawloader's CFA::color_at. E is the second green, or emerald, in
+// the few sensors which distinguish it
+const CFA_CHANNEL_NAMES: [&str; 4] = ["R", "G", "B", "E"];
+
+// Decoded raw sensor data, before any demosaicing or scaling
+pub(in crate) struct RawFrame {
+    pub image: RawImage,
+    data: Vec<u16>,
+}
+
+impl RawFrame {
+    pub fn decode<P: AsRef<Path>>(path: P) -> Result<RawFrame, Error> {
+        let mut image = rawloader::decode_file(path).map_err(|err| Error::Raw(err.to_string()))?;
+        // Take the samples, rather than copying them, since a stack holds many frames in memory
+        let data = match std::mem::replace(&mut image.data, RawImageData::Integer(vec![])) {
+            RawImageData::Integer(data) => data,
+            RawImageData::Float(_) => {
+                return Err(Error::Unsupported(
+                    "Floating point raw data is not supported".to_string(),
+                ))
+            }
+        };
+        Ok(RawFrame { image, data })
+    }
+
+    // Rows and columns which receive light, excluding the masked border
+    pub fn active_area(&self) -> (Range<usize>, Range<usize>) {
+        let [top, right, bottom, left] = self.image.crops;
+        (
+            top..(self.image.height - bottom),
+            left..(self.image.width - right),
+        )
+    }
+
+    // Regions of the sensor which are masked from light, as (rows, columns). Not known for every
+    // camera
+    pub fn masked_areas(&self) -> Vec<(Range<usize>, Range<usize>)> {
+        let (height, width) = (self.image.height, self.image.width);
+        self.image
+            .blackareas
+            .iter()
+            .map(|&(top, right, bottom, left)| {
+                (
+                    (top as usize).min(height)..(bottom as usize).min(height),
+                    (left as usize).min(width)..(right as usize).min(width),
+                )
+            })
+            .collect()
+    }
+
+    pub fn channel_names(&self) -> Vec<&'static str> {
+        if self.image.cpp == 1 {
+            CFA_CHANNEL_NAMES.to_vec()
+        } else {
+            CFA_CHANNEL_NAMES[..self.image.cpp].to_vec()
+        }
+    }
+
+    // All samples, in row major order with cpp samples per pixel
+    pub fn samples(&self) -> &[u16] {
+        &self.data
+    }
+
+    // Calls f with the row, column, channel index, and value of every sample in the given rows and
+    // columns
+    pub fn for_each_sample<F: FnMut(usize, usize, usize, u16)>(
+        &self,
+        rows: Range<usize>,
+        cols: Range<usize>,
+        mut f: F,
+    ) {
+        let cpp = self.image.cpp;
+        for row in rows {
+            for col in cols.clone() {
+                let index = (row * self.image.width + col) * cpp;
+                if cpp == 1 {
+                    f(
+                        row,
+                        col,
+                        self.image.cfa.color_at(row, col),
+                        self.data[index],
+                    );
+                } else {
+                    for sample in 0..cpp {
+                        f(row, col, sample, self.data[index + sample]);
+                    }
+                }
+            }
+        }
+    }
+}
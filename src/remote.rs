@@ -0,0 +1,53 @@
+use crate::error::Error;
+use crate::metadata::MetadataParser;
+use log::debug;
+use native_tls::TlsConnector;
+use std::io::Read;
+use std::sync::Arc;
+use ureq::{Agent, AgentBuilder};
+
+// EXIF data and maker notes are near the start of raw files, so this is usually enough
+const INITIAL_FETCH_BYTES: u64 = 64 * 1024;
+// Give up and report the parse error once this much of the file has been fetched
+const MAX_FETCH_BYTES: u64 = 16 * 1024 * 1024;
+
+pub(in crate) fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+fn fetch_prefix(agent: &Agent, url: &str, length: u64) -> Result<Vec<u8>, Error> {
+    let response = agent
+        .get(url)
+        .set("Range", &format!("bytes=0-{}", length - 1))
+        .call()
+        .map_err(Box::new)?;
+    // Servers which don't support range requests respond with the whole file, so stop reading
+    // once we have as much as was requested
+    let mut data = vec![];
+    response.into_reader().take(length).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+// Fetches the smallest prefix of the file, in increasing steps, which contains all the metadata.
+// S3 objects can be read through a presigned URL
+pub(in crate) fn fetch_metadata(parser: &MetadataParser, url: &str) -> Result<Vec<u8>, Error> {
+    let agent = AgentBuilder::new()
+        .tls_connector(Arc::new(TlsConnector::new()?))
+        .build();
+    let mut length = INITIAL_FETCH_BYTES;
+    loop {
+        let data = fetch_prefix(&agent, url, length)?;
+        let complete = (data.len() as u64) < length;
+        match parser.read_bytes(&data) {
+            Ok(_) => return Ok(data),
+            Err(err) if complete || length >= MAX_FETCH_BYTES => return Err(err),
+            Err(err) => {
+                debug!(
+                    "Failed to read metadata from first {} bytes of {}: {:?}",
+                    length, url, err
+                );
+                length *= 4;
+            }
+        }
+    }
+}
@@ -0,0 +1,466 @@
+use crate::error::Error;
+use crate::metadata::ImageMetadata;
+use crate::units::{round_tenths, Seconds};
+use crate::xmp::find_sidecar;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub(in crate) const DEFAULT_TEMPLATE: &str = "{model}_{iso}_{exposure}s_{temp}C_{datetime}.{ext}";
+pub(in crate) const DEFAULT_ORGANIZE_TEMPLATE: &str =
+    "{serial}/ISO{iso}/{exposure}s/{temp_bin}C/{name}.{ext}";
+
+pub(in crate) struct Template<'a> {
+    pub pattern: &'a str,
+    // Width of the ranges, in C, which {temp_bin} rounds the temperature down to
+    pub temperature_bin: f32,
+}
+
+pub(in crate) struct Rename {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    // XMP sidecar, which moves with the image
+    pub sidecar: Option<(PathBuf, PathBuf)>,
+}
+
+// Replaces characters which aren't safe in file names on all platforms. Values which would be
+// empty, or only dots, are replaced too, since as a directory name they'd put the file outside of
+// the destination
+fn sanitize(value: &str) -> String {
+    let sanitized: String = value
+        .trim()
+        .chars()
+        .map(|x| {
+            if x.is_ascii_alphanumeric() || x == '-' || x == '_' || x == '.' {
+                x
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    if sanitized.chars().all(|x| x == '.') {
+        "unknown".to_string()
+    } else {
+        sanitized
+    }
+}
+
+// Short exposures are written as 1-N, since a / can't be used in a file name
+fn format_exposure_time(exposure_time: Seconds) -> String {
+    if exposure_time.0 > 0.0 && exposure_time.0 < 1.0 {
+        format!("1-{}", exposure_time.reciprocal())
+    } else {
+        round_tenths(exposure_time.0).to_string()
+    }
+}
+
+impl<'a> Template<'a> {
+    fn field_value(
+        &self,
+        name: &str,
+        path: &Path,
+        metadata: &ImageMetadata,
+    ) -> Result<String, Error> {
+        let file_part = |x: Option<&std::ffi::OsStr>| {
+            x.map(|x| x.to_string_lossy().to_string())
+                .unwrap_or_default()
+        };
+        let capture_time = metadata.capture_time.as_deref().unwrap_or("unknown");
+        let value = match name {
+            "model" => sanitize(&metadata.camera_model),
+            "serial" => sanitize(&metadata.camera_serial_number),
+            "iso" => metadata.sensor_sensitivity.to_string(),
+            "exposure" => format_exposure_time(metadata.exposure_time),
+            "temp" => match metadata.temperature {
+                Some(x) => round_tenths(x.0).to_string(),
+                None => "unknown".to_string(),
+            },
+            "temp_bin" => match metadata.temperature {
+                Some(x) => round_tenths(x.bin(self.temperature_bin).0).to_string(),
+                None => "unknown".to_string(),
+            },
+            "datetime" => capture_time.replace(&['-', ':'][..], ""),
+            "date" => capture_time.split('T').next().unwrap().to_string(),
+            "name" => file_part(path.file_stem()),
+            "ext" => file_part(path.extension()),
+            _ => {
+                return Err(Error::InvalidArgument(format!(
+                    "Unknown template field: {{{}}}",
+                    name
+                )))
+            }
+        };
+        Ok(value)
+    }
+
+    // Replaces each {field} in the pattern with the value from the metadata
+    pub fn render(&self, path: &Path, metadata: &ImageMetadata) -> Result<String, Error> {
+        let mut rendered = String::new();
+        let mut rest = self.pattern;
+        while let Some(start) = rest.find('{') {
+            rendered.push_str(&rest[..start]);
+            let end = rest[start..].find('}').ok_or_else(|| {
+                Error::InvalidArgument(format!("Unclosed {{ in template: {}", self.pattern))
+            })?;
+            rendered.push_str(&self.field_value(
+                &rest[(start + 1)..(start + end)],
+                path,
+                metadata,
+            )?);
+            rest = &rest[(start + end + 1)..];
+        }
+        rendered.push_str(rest);
+        Ok(rendered)
+    }
+}
+
+// Sidecars keep their naming style: IMG_0001.xmp or IMG_0001.CR2.xmp
+fn sidecar_destination(from: &Path, sidecar: &Path, to: &Path) -> PathBuf {
+    if sidecar == from.with_extension("xmp") {
+        to.with_extension("xmp")
+    } else {
+        let mut appended = to.as_os_str().to_owned();
+        appended.push(".xmp");
+        PathBuf::from(appended)
+    }
+}
+
+// Appends _1, _2, etc to the file stem
+fn with_suffix(path: &Path, suffix: usize) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_owned();
+    name.push(format!("_{}", suffix));
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+// Picks the destination of each file. The rendered template is relative to destination(path).
+// Destinations which already exist, or are used by an earlier file, get a numeric suffix. Files
+// which would keep the same path are skipped
+pub(in crate) fn plan<F: Fn(&Path) -> PathBuf>(
+    files: &[(&str, ImageMetadata)],
+    template: &Template,
+    destination: F,
+) -> Result<Vec<Rename>, Error> {
+    let mut claimed = HashSet::new();
+    let mut renames = vec![];
+    for (path, metadata) in files.iter() {
+        let from = PathBuf::from(path);
+        let rendered = destination(&from).join(template.render(&from, metadata)?);
+        if rendered == from {
+            continue;
+        }
+        let sidecar = find_sidecar(&from);
+        let mut to = rendered.clone();
+        let mut suffix = 0;
+        loop {
+            let sidecar_taken =
+                matches!(&sidecar, Some(x) if sidecar_destination(&from, x, &to).exists());
+            if !to.exists() && !sidecar_taken && !claimed.contains(&to) {
+                break;
+            }
+            suffix += 1;
+            to = with_suffix(&rendered, suffix);
+        }
+        claimed.insert(to.clone());
+        renames.push(Rename {
+            sidecar: sidecar.map(|x| {
+                let destination = sidecar_destination(&from, &x, &to);
+                (x, destination)
+            }),
+            from,
+            to,
+        });
+    }
+    Ok(renames)
+}
+
+// EXDEV, or ERROR_NOT_SAME_DEVICE on Windows. std has no ErrorKind for it
+fn is_cross_device(err: &io::Error) -> bool {
+    let code = if cfg!(windows) { 17 } else { 18 };
+    err.raw_os_error() == Some(code)
+}
+
+fn move_file(from: &Path, to: &Path, copy: bool) -> Result<(), Error> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if copy {
+        fs::copy(from, to)?;
+        return Ok(());
+    }
+    match fs::rename(from, to) {
+        Ok(()) => Ok(()),
+        // Files can't be renamed to another filesystem, such as an external disk
+        Err(err) if is_cross_device(&err) => {
+            fs::copy(from, to)?;
+            if let Err(err) = fs::remove_file(from) {
+                // Leave only the original, so that the move either happened or didn't
+                let _ = fs::remove_file(to);
+                return Err(err.into());
+            }
+            Ok(())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+// Record of the files moved by apply(), so that they can be put back
+#[derive(Serialize, Deserialize)]
+pub(in crate) struct UndoLog {
+    pub copy: bool,
+    // (from, to) of each file, including sidecars, in the order they were moved
+    pub moves: Vec<(PathBuf, PathBuf)>,
+}
+
+impl UndoLog {
+    pub fn new(copy: bool) -> UndoLog {
+        UndoLog {
+            copy,
+            moves: vec![],
+        }
+    }
+}
+
+// Moves, or copies, each file and its sidecar. Each move is added to the log as soon as it's done,
+// so that if one fails, the log still has those before it
+pub(in crate) fn apply(renames: &[Rename], log: &mut UndoLog) -> Result<(), Error> {
+    for rename in renames.iter() {
+        move_file(&rename.from, &rename.to, log.copy)?;
+        log.moves.push((rename.from.clone(), rename.to.clone()));
+        if let Some((from, to)) = &rename.sidecar {
+            move_file(from, to, log.copy)?;
+            log.moves.push((from.clone(), to.clone()));
+        }
+    }
+    Ok(())
+}
+
+// Moves the files back, or deletes the copies. Directories which were created are left in place
+pub(in crate) fn undo(log: &UndoLog) -> Result<(), Error> {
+    for (from, to) in log.moves.iter().rev() {
+        if log.copy {
+            fs::remove_file(to)?;
+        } else {
+            move_file(to, from, false)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::Celsius;
+    use serde_json::json;
+    use std::env;
+    use std::process;
+
+    fn metadata(model: &str) -> ImageMetadata {
+        serde_json::from_value(json!({
+            "camera_make": "Canon",
+            "camera_model": model,
+            "camera_serial_number": "123",
+            "sensor_sensitivity": 800,
+            "sensitivity_type": 2,
+            "sensitivities": {},
+            "exposure_time": 300.0,
+            "temperature": 20.0,
+            "capture_time": "2021-03-04T22:10:11",
+            "confidence": {
+                "camera_model": "exact",
+                "camera_serial_number": "exact",
+                "sensor_sensitivity": "exact",
+                "exposure_time": "exact",
+                "temperature": "exact",
+                "capture_time": "exact"
+            },
+            "warnings": []
+        }))
+        .unwrap()
+    }
+
+    // Empty directory, unique to the test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("darkmagic-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn render_fields() {
+        let template = Template {
+            pattern: "{model}_{iso}_{exposure}s_{temp}C_{temp_bin}_{datetime}_{name}.{ext}",
+            temperature_bin: 5.0,
+        };
+        let mut metadata = metadata("EOS 6D");
+        metadata.temperature = Some(Celsius(17.25));
+        assert_eq!(
+            template
+                .render(Path::new("dir/IMG_0001.CR2"), &metadata)
+                .unwrap(),
+            "EOS-6D_800_300s_17.3C_15_20210304T221011_IMG_0001.CR2"
+        );
+
+        metadata.exposure_time = Seconds(0.004);
+        metadata.temperature = None;
+        let template = Template {
+            pattern: "{exposure}_{temp}",
+            temperature_bin: 5.0,
+        };
+        assert_eq!(
+            template.render(Path::new("a.cr2"), &metadata).unwrap(),
+            "1-250_unknown"
+        );
+    }
+
+    #[test]
+    fn render_rejects_bad_templates() {
+        for pattern in ["{model", "{model}_{unknown}"].iter() {
+            let template = Template {
+                pattern,
+                temperature_bin: 2.0,
+            };
+            let err = template
+                .render(Path::new("a.cr2"), &metadata("EOS"))
+                .err()
+                .unwrap();
+            assert!(matches!(err, Error::InvalidArgument(_)), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn values_cant_leave_the_destination() {
+        let template = Template {
+            pattern: "{serial}/{model}.{ext}",
+            temperature_bin: 2.0,
+        };
+        for value in ["..", ".", "", " / "].iter() {
+            let mut metadata = metadata(value);
+            metadata.camera_serial_number = value.to_string();
+            let rendered = template.render(Path::new("a.cr2"), &metadata).unwrap();
+            assert!(
+                Path::new(&rendered)
+                    .components()
+                    .all(|x| matches!(x, std::path::Component::Normal(_))),
+                "{:?} rendered as {}",
+                value,
+                rendered
+            );
+        }
+    }
+
+    #[test]
+    fn suffix_goes_before_the_extension() {
+        assert_eq!(
+            with_suffix(Path::new("dir/EOS.cr2"), 2),
+            PathBuf::from("dir/EOS_2.cr2")
+        );
+        assert_eq!(
+            with_suffix(Path::new("dir/EOS"), 1),
+            PathBuf::from("dir/EOS_1")
+        );
+    }
+
+    #[test]
+    fn plan_numbers_collisions() {
+        let dir = test_dir("plan");
+        let a = dir.join("a.cr2");
+        let b = dir.join("b.cr2");
+        let c = dir.join("c.cr2");
+        for path in [&a, &b, &c].iter() {
+            fs::write(path, b"").unwrap();
+        }
+        // Already taken by a file which isn't being renamed
+        fs::create_dir_all(dir.join("out")).unwrap();
+        fs::write(dir.join("out").join("EOS_1.cr2"), b"").unwrap();
+
+        let paths: Vec<String> = [&a, &b, &c]
+            .iter()
+            .map(|x| x.to_string_lossy().to_string())
+            .collect();
+        let files: Vec<(&str, ImageMetadata)> = paths
+            .iter()
+            .map(|x| (x.as_str(), metadata("EOS")))
+            .collect();
+        let template = Template {
+            pattern: "{model}.{ext}",
+            temperature_bin: 2.0,
+        };
+        let renames = plan(&files, &template, |_| dir.join("out")).unwrap();
+        let destinations: Vec<PathBuf> = renames.iter().map(|x| x.to.clone()).collect();
+        assert_eq!(
+            destinations,
+            vec![
+                dir.join("out").join("EOS.cr2"),
+                dir.join("out").join("EOS_2.cr2"),
+                dir.join("out").join("EOS_3.cr2"),
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn apply_and_undo_round_trip() {
+        for copy in [false, true].iter() {
+            let dir = test_dir(if *copy { "copy" } else { "move" });
+            let image = dir.join("a.cr2");
+            let sidecar = dir.join("a.xmp");
+            fs::write(&image, b"image").unwrap();
+            fs::write(&sidecar, b"sidecar").unwrap();
+            let path = image.to_string_lossy().to_string();
+            let files = vec![(path.as_str(), metadata("EOS"))];
+            let template = Template {
+                pattern: "{serial}/{model}.{ext}",
+                temperature_bin: 2.0,
+            };
+            let renames = plan(&files, &template, |_| dir.join("out")).unwrap();
+
+            let mut log = UndoLog::new(*copy);
+            apply(&renames, &mut log).unwrap();
+            let moved = dir.join("out").join("123").join("EOS.cr2");
+            assert_eq!(fs::read(&moved).unwrap(), b"image");
+            assert_eq!(fs::read(moved.with_extension("xmp")).unwrap(), b"sidecar");
+            assert_eq!(image.exists(), *copy);
+            assert_eq!(log.moves.len(), 2);
+
+            // The log is read back from JSON, as it would be by organize --undo
+            let log: UndoLog = serde_json::from_str(&serde_json::to_string(&log).unwrap()).unwrap();
+            undo(&log).unwrap();
+            assert_eq!(fs::read(&image).unwrap(), b"image");
+            assert_eq!(fs::read(&sidecar).unwrap(), b"sidecar");
+            assert!(!moved.exists());
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn failed_apply_keeps_earlier_moves() {
+        let dir = test_dir("partial");
+        let a = dir.join("a.cr2");
+        fs::write(&a, b"").unwrap();
+        let renames = vec![
+            Rename {
+                from: a.clone(),
+                to: dir.join("out").join("a.cr2"),
+                sidecar: None,
+            },
+            Rename {
+                from: dir.join("missing.cr2"),
+                to: dir.join("out").join("missing.cr2"),
+                sidecar: None,
+            },
+        ];
+        let mut log = UndoLog::new(false);
+        assert!(apply(&renames, &mut log).is_err());
+        assert_eq!(log.moves, vec![(a.clone(), dir.join("out").join("a.cr2"))]);
+        undo(&log).unwrap();
+        assert!(a.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
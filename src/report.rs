@@ -0,0 +1,256 @@
+use crate::error::Error;
+use crate::histogram::Histogram;
+use crate::metadata::ImageMetadata;
+use crate::units::{round_tenths, Celsius, TemperatureUnit};
+use serde_json::json;
+#[cfg(feature = "gpl")]
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+// Fields which can be binned by histogram_report()
+#[derive(Clone, Copy)]
+pub(in crate) enum NumericField {
+    Temperature,
+    Sensitivity,
+    ExposureTime,
+    ShutterCount,
+}
+
+impl NumericField {
+    pub fn parse(value: &str) -> Option<NumericField> {
+        match value {
+            "temperature" => Some(NumericField::Temperature),
+            "iso" => Some(NumericField::Sensitivity),
+            "exposure" => Some(NumericField::ExposureTime),
+            "shutter-count" => Some(NumericField::ShutterCount),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            NumericField::Temperature => "temperature",
+            NumericField::Sensitivity => "iso",
+            NumericField::ExposureTime => "exposure",
+            NumericField::ShutterCount => "shutter-count",
+        }
+    }
+
+    fn unit(self, temperature_unit: TemperatureUnit) -> &'static str {
+        match self {
+            NumericField::Temperature => temperature_unit.symbol(),
+            NumericField::ExposureTime => "s",
+            NumericField::Sensitivity | NumericField::ShutterCount => "",
+        }
+    }
+
+    // None if the frame doesn't record the field
+    fn value(self, metadata: &ImageMetadata, temperature_unit: TemperatureUnit) -> Option<f64> {
+        match self {
+            NumericField::Temperature => metadata
+                .temperature
+                .map(|x| f64::from(temperature_unit.convert(x))),
+            NumericField::Sensitivity => Some(f64::from(metadata.sensor_sensitivity)),
+            NumericField::ExposureTime => Some(f64::from(metadata.exposure_time.0)),
+            NumericField::ShutterCount => metadata.shutter_count.map(f64::from),
+        }
+    }
+}
+
+// Histogram of the field over the frames, as text or JSON. Frames which don't record the field are
+// counted, but not binned
+pub(in crate) fn histogram_report(
+    frames: &[(&str, ImageMetadata)],
+    field: NumericField,
+    bin_width: f64,
+    unit: TemperatureUnit,
+    json: bool,
+) -> Result<String, Error> {
+    let values: Vec<f64> = frames
+        .iter()
+        .filter_map(|(_, metadata)| field.value(metadata, unit))
+        .collect();
+    let missing = frames.len() - values.len();
+    let histogram = Histogram::new(&values, bin_width)?;
+    if json {
+        return Ok(json!({
+            "field": field.name(),
+            "unit": field.unit(unit),
+            "bin_width": histogram.bin_width,
+            "bins": histogram.bins,
+            "missing": missing,
+        })
+        .to_string());
+    }
+
+    let unit_suffix = match field.unit(unit) {
+        "" => String::new(),
+        x => format!(" {}", x),
+    };
+    let mut report = histogram.format_text(&unit_suffix);
+    writeln!(report).unwrap();
+    writeln!(report, "Frames: {}", frames.len()).unwrap();
+    if missing > 0 {
+        writeln!(report, "Without {}: {}", field.name(), missing).unwrap();
+    }
+    Ok(report)
+}
+
+// Temperature time series of the frames, ordered by capture time, followed by summary statistics.
+// Frames outside of the band [band_min, band_max] are flagged. Temperatures are printed in the
+// given unit
+pub(in crate) fn temperature_report(
+    frames: &[(&str, ImageMetadata)],
+    band_min: Option<Celsius>,
+    band_max: Option<Celsius>,
+    unit: TemperatureUnit,
+) -> String {
+    let mut sorted: Vec<&(&str, ImageMetadata)> = frames.iter().collect();
+    // Frames without a capture time go last
+    sorted.sort_by(|(_, a), (_, b)| match (&a.capture_time, &b.capture_time) {
+        (Some(a), Some(b)) => a.cmp(b),
+        (a, b) => b.is_some().cmp(&a.is_some()),
+    });
+
+    let mut report = String::new();
+    let mut outside_band = 0;
+    for (path, metadata) in sorted.iter() {
+        // Frames without a temperature can't be checked against the band
+        let too_cold = matches!((band_min, metadata.temperature), (Some(x), Some(y)) if y < x);
+        let too_hot = matches!((band_max, metadata.temperature), (Some(x), Some(y)) if y > x);
+        if too_cold || too_hot {
+            outside_band += 1;
+        }
+        writeln!(
+            report,
+            "{:<19}  {:>8}  {}{}",
+            metadata.capture_time.as_deref().unwrap_or("unknown"),
+            match metadata.temperature {
+                Some(x) => format!("{} {}", round_tenths(unit.convert(x)), unit.symbol()),
+                None => "unknown".to_string(),
+            },
+            path,
+            if too_cold || too_hot {
+                "  outside band"
+            } else {
+                ""
+            }
+        )
+        .unwrap();
+    }
+
+    writeln!(report).unwrap();
+    writeln!(report, "Frames: {}", frames.len()).unwrap();
+    let temperatures: Vec<f32> = frames
+        .iter()
+        .filter_map(|(_, x)| x.temperature.map(|x| unit.convert(x)))
+        .collect();
+    if temperatures.len() < frames.len() {
+        writeln!(
+            report,
+            "Without a temperature: {}",
+            frames.len() - temperatures.len()
+        )
+        .unwrap();
+    }
+    if !temperatures.is_empty() {
+        let min = temperatures.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = temperatures
+            .iter()
+            .copied()
+            .fold(f32::NEG_INFINITY, f32::max);
+        let mean = temperatures.iter().sum::<f32>() / temperatures.len() as f32;
+        writeln!(report, "Min: {} {}", round_tenths(min), unit.symbol()).unwrap();
+        writeln!(report, "Max: {} {}", round_tenths(max), unit.symbol()).unwrap();
+        writeln!(report, "Mean: {:.1} {}", mean, unit.symbol()).unwrap();
+    }
+    if band_min.is_some() || band_max.is_some() {
+        writeln!(report, "Outside band: {}", outside_band).unwrap();
+    }
+
+    report
+}
+
+// Least squares fit of y = slope * x + intercept. Returns the slope, intercept, and coefficient of
+// determination, or None if x doesn't vary
+#[cfg(feature = "gpl")]
+fn linear_fit(points: &[(f64, f64)]) -> Option<(f64, f64, f64)> {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let sxx: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    let sxy: f64 = points
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum();
+    let syy: f64 = points.iter().map(|(_, y)| (y - mean_y).powi(2)).sum();
+    if sxx == 0.0 {
+        return None;
+    }
+    let slope = sxy / sxx;
+    let r_squared = if syy == 0.0 {
+        1.0
+    } else {
+        sxy * sxy / (sxx * syy)
+    };
+    Some((slope, mean_y - slope * mean_x, r_squared))
+}
+
+// Dark current of each ISO, fit from the mean level of darks against their exposure time. The
+// frames are given as (path, metadata, mean level in ADU). The fit assumes the temperature was
+// constant, so the temperature range of each ISO is printed for checking. The result is in ADU,
+// since the gain of the sensor isn't known
+#[cfg(feature = "gpl")]
+pub(in crate) fn dark_current_report(
+    frames: &[(&str, ImageMetadata, f64)],
+    unit: TemperatureUnit,
+) -> String {
+    let mut by_iso: BTreeMap<u32, Vec<&(&str, ImageMetadata, f64)>> = BTreeMap::new();
+    for frame in frames {
+        by_iso
+            .entry(frame.1.sensor_sensitivity)
+            .or_default()
+            .push(frame);
+    }
+
+    let mut report = String::new();
+    for (iso, frames) in by_iso.iter() {
+        let range = |values: Vec<f32>| {
+            if values.is_empty() {
+                return "unknown".to_string();
+            }
+            let min = values.iter().copied().fold(f32::INFINITY, f32::min);
+            let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            format!("{}-{}", min, max)
+        };
+        writeln!(
+            report,
+            "ISO {}: {} frames, {} s, {} {}",
+            iso,
+            frames.len(),
+            range(frames.iter().map(|x| x.1.exposure_time.0).collect()),
+            range(
+                frames
+                    .iter()
+                    .filter_map(|x| x.1.temperature)
+                    .map(|x| round_tenths(unit.convert(x)) as f32)
+                    .collect()
+            ),
+            unit.symbol()
+        )
+        .unwrap();
+        let points: Vec<(f64, f64)> = frames
+            .iter()
+            .map(|(_, metadata, level)| (f64::from(metadata.exposure_time.0), *level))
+            .collect();
+        match linear_fit(&points) {
+            Some((slope, intercept, r_squared)) => {
+                writeln!(report, "  Dark current: {:.4} ADU/s", slope).unwrap();
+                writeln!(report, "  Offset: {:.1} ADU", intercept).unwrap();
+                writeln!(report, "  R^2: {:.4}", r_squared).unwrap();
+            }
+            None => writeln!(report, "  Needs frames with at least two exposure times").unwrap(),
+        }
+    }
+    report
+}
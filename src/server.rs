@@ -0,0 +1,78 @@
+use crate::error::Error;
+use crate::metadata::MetadataParser;
+use log::{info, warn};
+use serde_json::json;
+use std::io;
+use std::io::Read;
+use std::sync::Arc;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+// Uploads larger than this are truncated. Even the largest raws are well under this size
+const MAX_BODY_BYTES: u64 = 512 * 1024 * 1024;
+
+// POST /metadata
+//   Request body: the image file
+//   Response: 200 with the extracted metadata, for example
+//     {"camera_make": "Canon", "camera_model": "Canon EOS 6D",
+//      "camera_serial_number": "012345678901", "sensor_sensitivity": 1600, "sensitivity_type": 2,
+//      "sensitivities": {"standard_output_sensitivity": null,
+//                        "recommended_exposure_index": 1600, "iso_speed": null,
+//                        "iso_speed_latitude_yyy": null, "iso_speed_latitude_zzz": null,
+//                        "extended": null},
+//      "exposure_time": 300.0, "exposure_time_fraction": "300/1", "temperature": 17.0,
+//      "secondary_temperature": null, "capture_time": "2021-03-04T22:10:11",
+//      "shutter_count": null, "auto_sensitivity": false, "long_exposure_noise_reduction": false,
+//      "thumbnail": {"offset": 8948, "length": 10386, "compression": 6, "truncated": false},
+//      "confidence": {"camera_model": "exact", "camera_serial_number": "exact",
+//                     "sensor_sensitivity": "exact", "exposure_time": "exact",
+//                     "temperature": "exact", "capture_time": "exact"},
+//      "warnings": []}
+//   Warnings are either a name, like "legacy_sensitivity", or an object with a single key, like
+//   {"probed_sensitivity": "ISOSpeed"}. temperature is null for cameras which don't record it,
+//   and secondary_temperature for all but those which record two
+//   or 422 with {"error": "<description>", "code": <Error::code>} if the metadata could not be
+//   read
+fn handle_request(parser: &MetadataParser, mut request: Request) -> io::Result<()> {
+    let (status, body) = match (request.method(), request.url()) {
+        (Method::Post, "/metadata") => {
+            let mut data = vec![];
+            request
+                .as_reader()
+                .take(MAX_BODY_BYTES)
+                .read_to_end(&mut data)?;
+            match parser.read_bytes(&data) {
+                Ok(metadata) => (200, serde_json::to_string(&metadata).unwrap()),
+                Err(err) => (
+                    422,
+                    json!({ "error": err.to_string(), "code": err.code() }).to_string(),
+                ),
+            }
+        }
+        (_, "/metadata") => (405, json!({"error": "Method not allowed"}).to_string()),
+        _ => (404, json!({"error": "Not found"}).to_string()),
+    };
+    let content_type = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+    request.respond(
+        Response::from_string(body)
+            .with_status_code(status)
+            .with_header(content_type),
+    )
+}
+
+// Serves requests until interrupted with SIGINT or SIGTERM. Requests are handled one at a time,
+// so the one in progress is always completed before shutting down
+pub(in crate) fn serve(address: &str, parser: MetadataParser) -> Result<(), Error> {
+    let server = Arc::new(Server::http(address)?);
+    let shutdown_handle = server.clone();
+    ctrlc::set_handler(move || shutdown_handle.unblock())?;
+    info!("Listening on {}", address);
+
+    for request in server.incoming_requests() {
+        if let Err(err) = handle_request(&parser, request) {
+            warn!("Failed to respond to request: {}", err);
+        }
+    }
+    info!("Shutting down");
+
+    Ok(())
+}
@@ -0,0 +1,207 @@
+use crate::error::Error;
+use crate::fits;
+use crate::metadata::ImageMetadata;
+use crate::raw::RawFrame;
+use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+use exif::experimental::Writer;
+use exif::{Context, Field, In, Tag, Value};
+use std::io::{Seek, Write};
+
+// TIFF SampleFormat tag, which kamadak-exif doesn't define
+const TIFF_SAMPLE_FORMAT: u16 = 0x153;
+const SAMPLE_FORMAT_UINT: u16 = 1;
+const SAMPLE_FORMAT_FLOAT: u16 = 3;
+const PHOTOMETRIC_BLACK_IS_ZERO: u16 = 1;
+const COMPRESSION_NONE: u16 = 1;
+// Kappa-sigma clipping stops after this many rounds of rejection, even if it hasn't converged
+const MAX_CLIP_ITERATIONS: usize = 10;
+
+#[derive(Clone, Copy)]
+pub(in crate) enum Combine {
+    Mean,
+    Median,
+    // Mean after rejecting values more than kappa standard deviations from the mean
+    SigmaClip(f64),
+}
+
+#[derive(Clone, Copy)]
+pub(in crate) enum SampleFormat {
+    U16,
+    F32,
+}
+
+// Combined frame, with the same dimensions and CFA layout as the raw frames
+pub(in crate) struct MasterDark {
+    pub width: usize,
+    pub height: usize,
+    pub frames: usize,
+    pub data: Vec<f32>,
+}
+
+fn mean(values: &[u16]) -> f64 {
+    values.iter().map(|x| f64::from(*x)).sum::<f64>() / values.len() as f64
+}
+
+fn median(values: &mut [u16]) -> f64 {
+    values.sort_unstable();
+    let middle = values.len() / 2;
+    if values.len() % 2 == 1 {
+        f64::from(values[middle])
+    } else {
+        (f64::from(values[middle - 1]) + f64::from(values[middle])) / 2.0
+    }
+}
+
+fn sigma_clipped_mean(values: &mut Vec<u16>, kappa: f64) -> f64 {
+    for _ in 0..MAX_CLIP_ITERATIONS {
+        if values.len() < 3 {
+            break;
+        }
+        let center = mean(values);
+        let variance = values
+            .iter()
+            .map(|x| (f64::from(*x) - center).powi(2))
+            .sum::<f64>()
+            / values.len() as f64;
+        let limit = kappa * variance.sqrt();
+        let kept: Vec<u16> = values
+            .iter()
+            .copied()
+            .filter(|x| (f64::from(*x) - center).abs() <= limit)
+            .collect();
+        if kept.len() == values.len() || kept.is_empty() {
+            break;
+        }
+        *values = kept;
+    }
+    mean(values)
+}
+
+pub(in crate) fn stack(frames: &[RawFrame], combine: Combine) -> Result<MasterDark, Error> {
+    let first = &frames
+        .first()
+        .ok_or_else(|| Error::InvalidArgument("No frames to stack".to_string()))?
+        .image;
+    if first.cpp != 1 {
+        return Err(Error::Unsupported(
+            "Only raw files with a color filter array can be stacked".to_string(),
+        ));
+    }
+    if frames
+        .iter()
+        .any(|x| x.image.width != first.width || x.image.height != first.height)
+    {
+        return Err(Error::InvalidArgument(
+            "Frames have different dimensions".to_string(),
+        ));
+    }
+
+    let mut values = Vec::with_capacity(frames.len());
+    let data = (0..(first.width * first.height))
+        .map(|i| {
+            values.clear();
+            values.extend(frames.iter().map(|x| x.samples()[i]));
+            let value = match combine {
+                Combine::Mean => mean(&values),
+                Combine::Median => median(&mut values),
+                Combine::SigmaClip(kappa) => sigma_clipped_mean(&mut values, kappa),
+            };
+            value as f32
+        })
+        .collect();
+
+    Ok(MasterDark {
+        width: first.width,
+        height: first.height,
+        frames: frames.len(),
+        data,
+    })
+}
+
+fn to_u16(value: f32) -> u16 {
+    value.round().clamp(0.0, f32::from(u16::MAX)) as u16
+}
+
+pub(in crate) fn write_fits<W: Write>(
+    writer: &mut W,
+    master: &MasterDark,
+    format: SampleFormat,
+    metadata: Option<&ImageMetadata>,
+) -> Result<(), Error> {
+    let bitpix = match format {
+        SampleFormat::U16 => 16,
+        SampleFormat::F32 => -32,
+    };
+    writer.write_all(&fits::image_header(
+        bitpix,
+        master.width,
+        master.height,
+        master.frames,
+        metadata,
+    ))?;
+
+    let mut data = vec![];
+    for value in master.data.iter() {
+        match format {
+            // Shifted by BZERO, so that it fits in a signed integer
+            SampleFormat::U16 => {
+                data.write_i16::<BigEndian>((i32::from(to_u16(*value)) - 32768) as i16)?
+            }
+            SampleFormat::F32 => data.write_f32::<BigEndian>(*value)?,
+        }
+    }
+    let padding =
+        (fits::FITS_BLOCK_SIZE - data.len() % fits::FITS_BLOCK_SIZE) % fits::FITS_BLOCK_SIZE;
+    data.resize(data.len() + padding, 0);
+    writer.write_all(&data)?;
+    Ok(())
+}
+
+// Writes a single channel TIFF, with the whole image in one strip
+pub(in crate) fn write_tiff<W: Write + Seek>(
+    writer: &mut W,
+    master: &MasterDark,
+    format: SampleFormat,
+) -> Result<(), Error> {
+    let mut data = vec![];
+    for value in master.data.iter() {
+        match format {
+            SampleFormat::U16 => data.write_u16::<LittleEndian>(to_u16(*value))?,
+            SampleFormat::F32 => data.write_f32::<LittleEndian>(*value)?,
+        }
+    }
+    let (bits, sample_format) = match format {
+        SampleFormat::U16 => (16, SAMPLE_FORMAT_UINT),
+        SampleFormat::F32 => (32, SAMPLE_FORMAT_FLOAT),
+    };
+
+    let field = |tag, value| Field {
+        tag,
+        ifd_num: In::PRIMARY,
+        value,
+    };
+    let fields = [
+        field(Tag::ImageWidth, Value::Long(vec![master.width as u32])),
+        field(Tag::ImageLength, Value::Long(vec![master.height as u32])),
+        field(Tag::BitsPerSample, Value::Short(vec![bits])),
+        field(Tag::Compression, Value::Short(vec![COMPRESSION_NONE])),
+        field(
+            Tag::PhotometricInterpretation,
+            Value::Short(vec![PHOTOMETRIC_BLACK_IS_ZERO]),
+        ),
+        field(Tag::SamplesPerPixel, Value::Short(vec![1])),
+        field(Tag::RowsPerStrip, Value::Long(vec![master.height as u32])),
+        field(
+            Tag(Context::Tiff, TIFF_SAMPLE_FORMAT),
+            Value::Short(vec![sample_format]),
+        ),
+    ];
+    let strips = [&data[..]];
+    let mut tiff_writer = Writer::new();
+    for field in fields.iter() {
+        tiff_writer.push_field(field);
+    }
+    tiff_writer.set_strips(&strips, In::PRIMARY);
+    tiff_writer.write(writer, true)?;
+    Ok(())
+}
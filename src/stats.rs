@@ -0,0 +1,145 @@
+use crate::error::Error;
+use crate::raw::RawFrame;
+use std::fmt::Write;
+use std::ops::Range;
+
+const PERCENTILES: [f64; 6] = [1.0, 5.0, 25.0, 75.0, 95.0, 99.0];
+
+pub(in crate) struct ChannelStats {
+    // Index of the channel, as returned by CFA::color_at
+    pub channel: usize,
+    pub name: &'static str,
+    pub count: usize,
+    pub mean: f64,
+    pub median: u16,
+    pub std_dev: f64,
+    // Value at each of PERCENTILES
+    pub percentiles: Vec<u16>,
+}
+
+// Nearest rank percentile of sorted values
+pub(in crate) fn percentile(sorted: &[u16], percent: f64) -> u16 {
+    let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.max(1).min(sorted.len()) - 1]
+}
+
+fn summarize(channel: usize, name: &'static str, mut values: Vec<u16>) -> ChannelStats {
+    values.sort_unstable();
+    let count = values.len();
+    let mean = values.iter().map(|x| f64::from(*x)).sum::<f64>() / count as f64;
+    let variance = values
+        .iter()
+        .map(|x| (f64::from(*x) - mean).powi(2))
+        .sum::<f64>()
+        / count as f64;
+    ChannelStats {
+        channel,
+        name,
+        count,
+        mean,
+        median: percentile(&values, 50.0),
+        std_dev: variance.sqrt(),
+        percentiles: PERCENTILES
+            .iter()
+            .map(|x| percentile(&values, *x))
+            .collect(),
+    }
+}
+
+fn area_stats(frame: &RawFrame, areas: Vec<(Range<usize>, Range<usize>)>) -> Vec<ChannelStats> {
+    let names = frame.channel_names();
+    let mut channels: Vec<Vec<u16>> = vec![vec![]; names.len()];
+    for (rows, cols) in areas {
+        frame.for_each_sample(rows, cols, |_, _, channel, value| {
+            channels[channel].push(value)
+        });
+    }
+    names
+        .into_iter()
+        .zip(channels)
+        .enumerate()
+        .filter(|(_, (_, values))| !values.is_empty())
+        .map(|(channel, (name, values))| summarize(channel, name, values))
+        .collect()
+}
+
+// Statistics of the active area of the sensor, for each color channel
+pub(in crate) fn channel_stats(frame: &RawFrame) -> Vec<ChannelStats> {
+    area_stats(frame, vec![frame.active_area()])
+}
+
+// Mean of all samples in the active area
+pub(in crate) fn mean_level(frame: &RawFrame) -> f64 {
+    let mut sum = 0.0;
+    let mut count = 0;
+    let (rows, cols) = frame.active_area();
+    frame.for_each_sample(rows, cols, |_, _, _, value| {
+        sum += f64::from(value);
+        count += 1;
+    });
+    sum / count as f64
+}
+
+// Statistics of the masked pixels, which measure the bias level, for each color channel
+pub(in crate) fn masked_stats(frame: &RawFrame) -> Result<Vec<ChannelStats>, Error> {
+    let stats = area_stats(frame, frame.masked_areas());
+    if stats.is_empty() {
+        return Err(Error::Unsupported(format!(
+            "Masked pixels of {} {} are not known",
+            frame.image.make, frame.image.model
+        )));
+    }
+    Ok(stats)
+}
+
+pub(in crate) fn format_stats(stats: &[ChannelStats]) -> String {
+    let mut output = String::new();
+    write!(
+        output,
+        "{:<8}{:>10}{:>10}{:>8}{:>9}",
+        "Channel", "Pixels", "Mean", "Median", "StdDev"
+    )
+    .unwrap();
+    for percent in PERCENTILES.iter() {
+        write!(output, "{:>7}", format!("P{}", percent)).unwrap();
+    }
+    writeln!(output).unwrap();
+    for channel in stats {
+        write!(
+            output,
+            "{:<8}{:>10}{:>10.1}{:>8}{:>9.2}",
+            channel.name, channel.count, channel.mean, channel.median, channel.std_dev
+        )
+        .unwrap();
+        for value in channel.percentiles.iter() {
+            write!(output, "{:>7}", value).unwrap();
+        }
+        writeln!(output).unwrap();
+    }
+    output
+}
+
+// Compares the measured bias level to the black level recorded for the camera
+pub(in crate) fn format_bias(stats: &[ChannelStats], frame: &RawFrame) -> String {
+    let mut output = String::new();
+    writeln!(
+        output,
+        "{:<8}{:>10}{:>10}{:>8}{:>9}{:>9}",
+        "Channel", "Pixels", "Mean", "Median", "StdDev", "Black"
+    )
+    .unwrap();
+    for channel in stats {
+        writeln!(
+            output,
+            "{:<8}{:>10}{:>10.1}{:>8}{:>9.2}{:>9}",
+            channel.name,
+            channel.count,
+            channel.mean,
+            channel.median,
+            channel.std_dev,
+            frame.image.blacklevels[channel.channel]
+        )
+        .unwrap();
+    }
+    output
+}
@@ -0,0 +1,110 @@
+use crate::error::Error;
+use crate::histogram::{format_bars, Histogram};
+use crate::metadata::ImageMetadata;
+use crate::units::{Celsius, Seconds, TemperatureUnit};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+// Width of the temperature histogram's bins, in the display unit
+const TEMPERATURE_BIN_WIDTH: f64 = 1.0;
+
+// Composition of the files read by a batch run, printed at the end with --summary
+#[derive(Default)]
+pub(in crate) struct Summary {
+    models: BTreeMap<String, usize>,
+    sensitivities: BTreeMap<u32, usize>,
+    // Only of the files which record it
+    temperatures: Vec<Celsius>,
+    // One per file read
+    exposure_times: Vec<Seconds>,
+    failures: BTreeMap<&'static str, usize>,
+}
+
+impl Summary {
+    pub fn add(&mut self, metadata: &ImageMetadata) {
+        *self
+            .models
+            .entry(metadata.camera_model.clone())
+            .or_default() += 1;
+        *self
+            .sensitivities
+            .entry(metadata.sensor_sensitivity)
+            .or_default() += 1;
+        if let Some(temperature) = metadata.temperature {
+            self.temperatures.push(temperature);
+        }
+        self.exposure_times.push(metadata.exposure_time);
+    }
+
+    pub fn add_failure(&mut self, error: &Error) {
+        *self.failures.entry(error.kind()).or_default() += 1;
+    }
+
+    // Exposure times are grouped as the camera displays them, since they're set from a fixed list
+    fn exposure_time_counts(&self) -> Vec<(String, usize)> {
+        let mut exposure_times = self.exposure_times.clone();
+        exposure_times.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let mut counts: Vec<(String, usize)> = vec![];
+        for exposure_time in exposure_times {
+            let label = format!("{} s", exposure_time.shutter_speed());
+            match counts.last_mut() {
+                Some((last, count)) if *last == label => *count += 1,
+                _ => counts.push((label, 1)),
+            }
+        }
+        counts
+    }
+
+    pub fn format(&self, unit: TemperatureUnit) -> String {
+        let mut result = String::new();
+        writeln!(
+            result,
+            "Files: {} read, {} failed",
+            self.exposure_times.len(),
+            self.failures.values().sum::<usize>()
+        )
+        .unwrap();
+        if !self.exposure_times.is_empty() {
+            let models: Vec<(String, usize)> = self
+                .models
+                .iter()
+                .map(|(model, count)| (model.clone(), *count))
+                .collect();
+            writeln!(result, "Camera models:\n{}", format_bars(&models)).unwrap();
+
+            let sensitivities: Vec<(String, usize)> = self
+                .sensitivities
+                .iter()
+                .map(|(sensitivity, count)| (sensitivity.to_string(), *count))
+                .collect();
+            writeln!(result, "Sensitivities:\n{}", format_bars(&sensitivities)).unwrap();
+
+            let temperatures: Vec<f64> = self
+                .temperatures
+                .iter()
+                .map(|x| f64::from(unit.convert(*x)))
+                .collect();
+            let histogram = match Histogram::new(&temperatures, TEMPERATURE_BIN_WIDTH) {
+                Ok(histogram) => histogram.format_text(&format!(" {}", unit.symbol())),
+                Err(err) => format!("  {}\n", err),
+            };
+            writeln!(result, "Temperatures:\n{}", histogram).unwrap();
+
+            writeln!(
+                result,
+                "Exposure times:\n{}",
+                format_bars(&self.exposure_time_counts())
+            )
+            .unwrap();
+        }
+        if !self.failures.is_empty() {
+            let failures: Vec<(String, usize)> = self
+                .failures
+                .iter()
+                .map(|(kind, count)| (kind.to_string(), *count))
+                .collect();
+            writeln!(result, "Failures:\n{}", format_bars(&failures)).unwrap();
+        }
+        result
+    }
+}
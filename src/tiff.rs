@@ -0,0 +1,670 @@
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use exif::{Rational, SRational, Value};
+use log::{debug, warn};
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+use std::io::{Error, ErrorKind};
+
+// See: https://www.media.mit.edu/pia/Research/deepview/exif.html#DataForm
+const TYPE_UBYTE: u16 = 1;
+const TYPE_ASCII: u16 = 2;
+const TYPE_USHORT: u16 = 3;
+const TYPE_ULONG: u16 = 4;
+const TYPE_URATIONAL: u16 = 5;
+const TYPE_BYTE: u16 = 6;
+const TYPE_UNDEFINED: u16 = 7;
+const TYPE_SHORT: u16 = 8;
+const TYPE_LONG: u16 = 9;
+const TYPE_RATIONAL: u16 = 10;
+const TYPE_FLOAT: u16 = 11;
+const TYPE_DOUBLE: u16 = 12;
+// Offset of a sub-IFD. See: TIFF Technical Note 1
+const TYPE_IFD: u16 = 13;
+
+// Byte order markers, "MM" and "II", as read in big endian
+const IFD_BIG_ENDIAN: u16 = 0x4d4d;
+const IFD_LITTLE_ENDIAN: u16 = 0x4949;
+pub(in crate) const TIFF_MAGIC: u16 = 42;
+
+// Byte order marker, magic number, and offset of the first IFD
+pub(in crate) const TIFF_HEADER_BYTES: usize = 8;
+// Tag, type, count, and value or offset
+pub(in crate) const IFD_ENTRY_BYTES: usize = 12;
+// Values of at most this many bytes are stored in the entry itself, instead of at an offset
+const INLINE_VALUE_BYTES: usize = 4;
+// Larger than any value cameras write in their maker notes, but small enough that a crafted entry
+// can't exhaust memory
+const DEFAULT_MAX_VALUE_BYTES: usize = 1024 * 1024;
+
+// How malformed entries in an IFD are handled
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(in crate) enum ParseMode {
+    // Any malformed entry fails the whole IFD
+    Strict,
+    // Malformed entries are skipped with a warning, and the rest are still read
+    Permissive,
+}
+
+#[derive(Clone, Copy)]
+pub(in crate) struct ParseOptions {
+    pub mode: ParseMode,
+    // Entries with values larger than this are rejected before they're decoded
+    pub max_value_bytes: usize,
+}
+
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions {
+            mode: ParseMode::Strict,
+            max_value_bytes: DEFAULT_MAX_VALUE_BYTES,
+        }
+    }
+}
+
+// Returned, wrapped in an io::Error, for entries larger than ParseOptions::max_value_bytes
+#[derive(Debug)]
+pub(in crate) struct ValueTooLarge {
+    pub tag: u16,
+    pub bytes: usize,
+    pub limit: usize,
+}
+
+impl fmt::Display for ValueTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Value of tag {:#06x} is {} bytes, which is more than the limit of {}",
+            self.tag, self.bytes, self.limit
+        )
+    }
+}
+
+impl std::error::Error for ValueTooLarge {}
+
+// Returned, wrapped in an io::Error, for IFDs and maker notes which are damaged or aren't in the
+// format expected
+#[derive(Debug)]
+pub(in crate) struct CorruptIfd {
+    // Relative to the start of the buffer being parsed
+    pub offset: usize,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for CorruptIfd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Corrupt IFD at offset {}: {}", self.offset, self.reason)
+    }
+}
+
+impl std::error::Error for CorruptIfd {}
+
+pub(in crate) fn corrupt_ifd(offset: usize, reason: &'static str) -> Error {
+    Error::new(ErrorKind::InvalidData, CorruptIfd { offset, reason })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(in crate) enum Endian {
+    Little,
+    Big,
+}
+
+impl Endian {
+    pub fn new(little_endian: bool) -> Endian {
+        if little_endian {
+            Endian::Little
+        } else {
+            Endian::Big
+        }
+    }
+
+    // Reads a byte order marker, "II" or "MM"
+    pub fn from_marker(data: &[u8]) -> Option<Endian> {
+        if data.len() < 2 {
+            return None;
+        }
+        match BigEndian::read_u16(data) {
+            IFD_LITTLE_ENDIAN => Some(Endian::Little),
+            IFD_BIG_ENDIAN => Some(Endian::Big),
+            _ => None,
+        }
+    }
+
+    pub fn read_u16(self, data: &[u8]) -> u16 {
+        match self {
+            Endian::Little => LittleEndian::read_u16(data),
+            Endian::Big => BigEndian::read_u16(data),
+        }
+    }
+
+    pub fn read_u32(self, data: &[u8]) -> u32 {
+        match self {
+            Endian::Little => LittleEndian::read_u32(data),
+            Endian::Big => BigEndian::read_u32(data),
+        }
+    }
+
+    fn parse_value(self, data_type: u16, data: &[u8]) -> io::Result<Value> {
+        match self {
+            Endian::Little => parse_value::<LittleEndian>(data_type, data),
+            Endian::Big => parse_value::<BigEndian>(data_type, data),
+        }
+    }
+}
+
+pub(in crate) struct IfdEntry {
+    pub tag: u16,
+    pub value: Value,
+}
+
+// Entry which was skipped in permissive mode, because it was malformed
+#[derive(Clone, Debug)]
+pub(in crate) struct SkippedEntry {
+    // None if the entry was truncated before its tag
+    pub tag: Option<u16>,
+    pub reason: String,
+}
+
+pub(in crate) struct Ifd {
+    pub entries: Vec<IfdEntry>,
+    // Always empty in strict mode
+    pub skipped: Vec<SkippedEntry>,
+    // Offset of the next IFD in the chain, if there is one
+    pub next: Option<usize>,
+}
+
+impl Ifd {
+    pub fn find(&self, tag: u16) -> Option<&Value> {
+        self.entries.iter().find(|x| x.tag == tag).map(|x| &x.value)
+    }
+}
+
+// Reads IFDs out of a buffer. IFD offsets are relative to the start of the buffer, while offsets
+// stored in the IFDs are shifted by pointer_fixup first, for structures like the Canon maker note
+// which were moved after their offsets were written
+#[derive(Clone, Copy)]
+pub(in crate) struct IfdReader<'a> {
+    data: &'a [u8],
+    endian: Endian,
+    pointer_fixup: isize,
+    options: ParseOptions,
+}
+
+impl<'a> IfdReader<'a> {
+    pub fn new(data: &'a [u8], endian: Endian, options: ParseOptions) -> IfdReader<'a> {
+        IfdReader {
+            data,
+            endian,
+            pointer_fixup: 0,
+            options,
+        }
+    }
+
+    pub fn with_pointer_fixup(self, pointer_fixup: isize) -> IfdReader<'a> {
+        IfdReader {
+            pointer_fixup,
+            ..self
+        }
+    }
+
+    // Reads the TIFF header at the start of the buffer. Returns a reader in its byte order, and the
+    // offset of the first IFD
+    pub fn from_tiff_header(
+        data: &'a [u8],
+        options: ParseOptions,
+    ) -> io::Result<(IfdReader<'a>, usize)> {
+        if data.len() < TIFF_HEADER_BYTES {
+            return Err(corrupt_ifd(0, "TIFF header is truncated"));
+        }
+        let endian =
+            Endian::from_marker(data).ok_or_else(|| corrupt_ifd(0, "invalid byte order marker"))?;
+        if endian.read_u16(&data[2..]) != TIFF_MAGIC {
+            return Err(corrupt_ifd(2, "invalid TIFF magic number"));
+        }
+        let ifd_offset = endian.read_u32(&data[4..]) as usize;
+        Ok((IfdReader::new(data, endian, options), ifd_offset))
+    }
+
+    pub fn read_ifd(&self, offset: usize) -> io::Result<Ifd> {
+        let count_end = offset
+            .checked_add(2)
+            .filter(|x| *x <= self.data.len())
+            .ok_or_else(|| corrupt_ifd(offset, "IFD is past the end of the data"))?;
+        let entry_count = self.endian.read_u16(&self.data[offset..]) as usize;
+
+        let mut entries = vec![];
+        let mut skipped = vec![];
+        for i in 0..entry_count {
+            let entry_start = count_end + i * IFD_ENTRY_BYTES;
+            if self.options.mode == ParseMode::Permissive
+                && entry_start + IFD_ENTRY_BYTES > self.data.len()
+            {
+                let reason = format!("IFD is truncated after {} of {} entries", i, entry_count);
+                debug!("{}", reason);
+                skipped.push(SkippedEntry {
+                    tag: self
+                        .data
+                        .get(entry_start..entry_start + 2)
+                        .map(|x| self.endian.read_u16(x)),
+                    reason,
+                });
+                return Ok(Ifd {
+                    entries,
+                    skipped,
+                    next: None,
+                });
+            }
+            match self.read_entry(entry_start) {
+                Ok(entry) => entries.push(entry),
+                Err(err) if self.options.mode == ParseMode::Permissive => {
+                    debug!("Skipping malformed IFD entry {}: {}", i, err);
+                    skipped.push(SkippedEntry {
+                        tag: Some(self.endian.read_u16(&self.data[entry_start..])),
+                        reason: err.to_string(),
+                    });
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        // Many maker notes end right after their last entry, without a next IFD offset
+        let next_start = count_end + entry_count * IFD_ENTRY_BYTES;
+        let next = self
+            .data
+            .get(next_start..next_start + 4)
+            .map(|x| self.endian.read_u32(x))
+            .filter(|x| *x != 0)
+            .and_then(|x| self.fix_pointer(x));
+
+        Ok(Ifd {
+            entries,
+            skipped,
+            next,
+        })
+    }
+
+    // The IFD at the given offset, followed by every IFD chained after it
+    pub fn chain(&self, offset: usize) -> IfdChain<'a> {
+        IfdChain {
+            reader: *self,
+            next: Some(offset),
+            visited: HashSet::new(),
+        }
+    }
+
+    // Converts an offset stored in an IFD to an index in to the buffer, or None if it points before
+    // the start
+    fn fix_pointer(&self, pointer: u32) -> Option<usize> {
+        let pointer = (pointer as isize).checked_add(self.pointer_fixup)?;
+        if pointer < 0 {
+            None
+        } else {
+            Some(pointer as usize)
+        }
+    }
+
+    fn read_entry(&self, start: usize) -> io::Result<IfdEntry> {
+        let entry = self
+            .data
+            .get(start..start + IFD_ENTRY_BYTES)
+            .ok_or_else(|| corrupt_ifd(start, "IFD entry is truncated"))?;
+        let tag = self.endian.read_u16(entry);
+        let value_type = self.endian.read_u16(&entry[2..]);
+        let element_count = self.endian.read_u32(&entry[4..]);
+        let data_bytes = type_width(value_type)
+            .map_err(|_| corrupt_ifd(start, "unknown value type"))?
+            .checked_mul(element_count as usize)
+            .ok_or_else(|| corrupt_ifd(start, "value size overflows"))?;
+        if data_bytes > self.options.max_value_bytes {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                ValueTooLarge {
+                    tag,
+                    bytes: data_bytes,
+                    limit: self.options.max_value_bytes,
+                },
+            ));
+        }
+        let data = if data_bytes <= INLINE_VALUE_BYTES {
+            &entry[8..(8 + data_bytes)]
+        } else {
+            let pointer = self
+                .fix_pointer(self.endian.read_u32(&entry[8..]))
+                .ok_or_else(|| corrupt_ifd(start, "value is before the start of the data"))?;
+            pointer
+                .checked_add(data_bytes)
+                .and_then(|end| self.data.get(pointer..end))
+                .ok_or_else(|| corrupt_ifd(start, "value is past the end of the data"))?
+        };
+        Ok(IfdEntry {
+            tag,
+            value: self.endian.parse_value(value_type, data)?,
+        })
+    }
+}
+
+// Iterates over a chain of IFDs. Stops at the first offset which was already visited, since
+// damaged files can contain loops
+pub(in crate) struct IfdChain<'a> {
+    reader: IfdReader<'a>,
+    next: Option<usize>,
+    visited: HashSet<usize>,
+}
+
+impl<'a> Iterator for IfdChain<'a> {
+    type Item = io::Result<Ifd>;
+
+    fn next(&mut self) -> Option<io::Result<Ifd>> {
+        let offset = self.next.take()?;
+        if !self.visited.insert(offset) {
+            warn!("IFD chain loops back to offset {}", offset);
+            return None;
+        }
+        let result = self.reader.read_ifd(offset);
+        if let Ok(ifd) = &result {
+            self.next = ifd.next;
+        }
+        Some(result)
+    }
+}
+
+fn parse_value<E: ByteOrder>(data_type: u16, data: &[u8]) -> io::Result<Value> {
+    Ok(match data_type {
+        TYPE_BYTE => Value::SByte(data.iter().map(|x| *x as i8).collect()),
+        TYPE_UBYTE => Value::Byte(data.to_vec()),
+        TYPE_ASCII => Value::Ascii(data.split(|x| *x == 0).map(|x| x.to_vec()).collect()),
+        // TODO: is it safe to pass zero here?
+        TYPE_UNDEFINED => Value::Undefined(data.to_vec(), 0),
+        TYPE_SHORT => {
+            let mut value = vec![0i16; data.len() / type_width(data_type)?];
+            E::read_i16_into(data, &mut value);
+            Value::SShort(value)
+        }
+        TYPE_USHORT => {
+            let mut value = vec![0u16; data.len() / type_width(data_type)?];
+            E::read_u16_into(data, &mut value);
+            Value::Short(value)
+        }
+        TYPE_LONG => {
+            let mut value = vec![0i32; data.len() / type_width(data_type)?];
+            E::read_i32_into(data, &mut value);
+            Value::SLong(value)
+        }
+        TYPE_ULONG | TYPE_IFD => {
+            let mut value = vec![0u32; data.len() / type_width(data_type)?];
+            E::read_u32_into(data, &mut value);
+            Value::Long(value)
+        }
+        TYPE_FLOAT => {
+            let mut value = vec![0f32; data.len() / type_width(data_type)?];
+            E::read_f32_into(data, &mut value);
+            Value::Float(value)
+        }
+        // Rationals are stored as pairs of numerator and denominator
+        TYPE_RATIONAL => {
+            let mut value = vec![0i32; 2 * data.len() / type_width(data_type)?];
+            E::read_i32_into(data, &mut value);
+            Value::SRational(
+                value
+                    .chunks_exact(2)
+                    .map(|x| SRational::from((x[0], x[1])))
+                    .collect(),
+            )
+        }
+        TYPE_URATIONAL => {
+            let mut value = vec![0u32; 2 * data.len() / type_width(data_type)?];
+            E::read_u32_into(data, &mut value);
+            Value::Rational(
+                value
+                    .chunks_exact(2)
+                    .map(|x| Rational::from((x[0], x[1])))
+                    .collect(),
+            )
+        }
+        TYPE_DOUBLE => {
+            let mut value = vec![0f64; data.len() / type_width(data_type)?];
+            E::read_f64_into(data, &mut value);
+            Value::Double(value)
+        }
+        _ => return Err(Error::from(ErrorKind::InvalidInput)),
+    })
+}
+
+pub(in crate) fn type_width(data_type: u16) -> io::Result<usize> {
+    Ok(match data_type {
+        TYPE_BYTE | TYPE_UBYTE | TYPE_ASCII | TYPE_UNDEFINED => 1,
+        TYPE_SHORT | TYPE_USHORT => 2,
+        TYPE_LONG | TYPE_ULONG | TYPE_FLOAT | TYPE_IFD => 4,
+        TYPE_RATIONAL | TYPE_URATIONAL | TYPE_DOUBLE => 8,
+        _ => return Err(Error::from(ErrorKind::InvalidData)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TAG_A: u16 = 0x100;
+    const TAG_B: u16 = 0x101;
+
+    fn u16_bytes(endian: Endian, value: u16) -> [u8; 2] {
+        let mut bytes = [0; 2];
+        match endian {
+            Endian::Little => LittleEndian::write_u16(&mut bytes, value),
+            Endian::Big => BigEndian::write_u16(&mut bytes, value),
+        }
+        bytes
+    }
+
+    fn u32_bytes(endian: Endian, value: u32) -> [u8; 4] {
+        let mut bytes = [0; 4];
+        match endian {
+            Endian::Little => LittleEndian::write_u32(&mut bytes, value),
+            Endian::Big => BigEndian::write_u32(&mut bytes, value),
+        }
+        bytes
+    }
+
+    // A single SHORT, padded to the width of the value field
+    fn short_value(endian: Endian, value: u16) -> [u8; 4] {
+        let bytes = u16_bytes(endian, value);
+        [bytes[0], bytes[1], 0, 0]
+    }
+
+    // IFD of (tag, type, count, value or offset) entries, followed by the offset of the next IFD
+    fn ifd(endian: Endian, entries: &[(u16, u16, u32, [u8; 4])], next: u32) -> Vec<u8> {
+        let mut data = u16_bytes(endian, entries.len() as u16).to_vec();
+        for (tag, value_type, count, value) in entries {
+            data.extend_from_slice(&u16_bytes(endian, *tag));
+            data.extend_from_slice(&u16_bytes(endian, *value_type));
+            data.extend_from_slice(&u32_bytes(endian, *count));
+            data.extend_from_slice(value);
+        }
+        data.extend_from_slice(&u32_bytes(endian, next));
+        data
+    }
+
+    fn permissive() -> ParseOptions {
+        ParseOptions {
+            mode: ParseMode::Permissive,
+            ..ParseOptions::default()
+        }
+    }
+
+    fn corrupt_reason(err: &Error) -> Option<&'static str> {
+        err.get_ref()
+            .and_then(|x| x.downcast_ref::<CorruptIfd>())
+            .map(|x| x.reason)
+    }
+
+    #[test]
+    fn reads_both_byte_orders() {
+        for (endian, marker) in [(Endian::Little, b"II*\0"), (Endian::Big, b"MM\0*")].iter() {
+            let mut data = marker.to_vec();
+            data.extend_from_slice(&u32_bytes(*endian, 8));
+            data.extend(ifd(
+                *endian,
+                &[
+                    (TAG_A, TYPE_USHORT, 1, short_value(*endian, 7)),
+                    (TAG_B, TYPE_ULONG, 1, u32_bytes(*endian, 70000)),
+                ],
+                0,
+            ));
+            let (reader, offset) = IfdReader::from_tiff_header(&data, ParseOptions::default())
+                .unwrap_or_else(|err| panic!("{:?}: {}", endian, err));
+            assert_eq!(reader.endian, *endian);
+            let ifd = reader.read_ifd(offset).unwrap();
+            assert!(matches!(ifd.find(TAG_A), Some(Value::Short(x)) if x == &[7]));
+            assert!(matches!(ifd.find(TAG_B), Some(Value::Long(x)) if x == &[70000]));
+            assert_eq!(ifd.next, None);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_tiff_headers() {
+        let options = ParseOptions::default();
+        for header in [&b"II*\0"[..], b"XX*\0\x08\0\0\0", b"II\0*\x08\0\0\0"].iter() {
+            let err = IfdReader::from_tiff_header(header, options).err().unwrap();
+            assert!(corrupt_reason(&err).is_some(), "{:?}", header);
+        }
+    }
+
+    #[test]
+    fn applies_pointer_fixup() {
+        // Out of line value at offset 18, recorded as if the IFD had been 100 bytes further in
+        let endian = Endian::Little;
+        let mut data = ifd(endian, &[(TAG_A, TYPE_ASCII, 6, u32_bytes(endian, 118))], 0);
+        data.extend_from_slice(b"Nikon\0");
+        let reader = IfdReader::new(&data, endian, ParseOptions::default());
+        let ifd = reader.with_pointer_fixup(-100).read_ifd(0).unwrap();
+        assert!(matches!(ifd.find(TAG_A), Some(Value::Ascii(x)) if x[0] == b"Nikon"));
+
+        // Without the fixup, the value is past the end of the data
+        let err = reader.read_ifd(0).err().unwrap();
+        assert_eq!(
+            corrupt_reason(&err),
+            Some("value is past the end of the data")
+        );
+
+        // And with too large a fixup, it's before the start
+        let err = reader.with_pointer_fixup(-200).read_ifd(0).err().unwrap();
+        assert_eq!(
+            corrupt_reason(&err),
+            Some("value is before the start of the data")
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_bounds_counts() {
+        let endian = Endian::Big;
+        // 100 SHORTs is far more than the buffer holds
+        let data = ifd(
+            endian,
+            &[(TAG_A, TYPE_USHORT, 100, u32_bytes(endian, 2))],
+            0,
+        );
+        let err = IfdReader::new(&data, endian, ParseOptions::default())
+            .read_ifd(0)
+            .err()
+            .unwrap();
+        assert_eq!(
+            corrupt_reason(&err),
+            Some("value is past the end of the data")
+        );
+
+        // An entry count which runs past the end of the buffer
+        let mut data = ifd(
+            endian,
+            &[(TAG_A, TYPE_USHORT, 1, short_value(endian, 1))],
+            0,
+        );
+        data[..2].copy_from_slice(&u16_bytes(endian, 3));
+        data.truncate(2 + IFD_ENTRY_BYTES);
+        let err = IfdReader::new(&data, endian, ParseOptions::default())
+            .read_ifd(0)
+            .err()
+            .unwrap();
+        assert_eq!(corrupt_reason(&err), Some("IFD entry is truncated"));
+        let ifd = IfdReader::new(&data, endian, permissive())
+            .read_ifd(0)
+            .unwrap();
+        assert_eq!(ifd.entries.len(), 1);
+        assert_eq!(ifd.skipped.len(), 1);
+
+        let err = IfdReader::new(&data, endian, ParseOptions::default())
+            .read_ifd(data.len())
+            .err()
+            .unwrap();
+        assert_eq!(
+            corrupt_reason(&err),
+            Some("IFD is past the end of the data")
+        );
+    }
+
+    #[test]
+    fn stops_at_loops_in_chain() {
+        let endian = Endian::Little;
+        let entries = [(TAG_A, TYPE_USHORT, 1, short_value(endian, 1))];
+        // Two IFDs at offsets 4 and 22, each pointing at the other
+        let mut data = vec![0; 4];
+        data.extend(ifd(endian, &entries, 22));
+        data.extend(ifd(endian, &entries, 4));
+        let ifds: Vec<Ifd> = IfdReader::new(&data, endian, ParseOptions::default())
+            .chain(4)
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(ifds.len(), 2);
+
+        // An IFD which points at itself
+        let mut data = vec![0; 4];
+        data.extend(ifd(endian, &entries, 4));
+        let ifds: Vec<Ifd> = IfdReader::new(&data, endian, ParseOptions::default())
+            .chain(4)
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(ifds.len(), 1);
+    }
+
+    #[test]
+    fn enforces_max_value_bytes() {
+        let endian = Endian::Little;
+        let mut data = ifd(
+            endian,
+            &[
+                (TAG_A, TYPE_UNDEFINED, 16, u32_bytes(endian, 30)),
+                (TAG_B, TYPE_USHORT, 1, short_value(endian, 3)),
+            ],
+            0,
+        );
+        data.extend_from_slice(&[0; 16]);
+        let options = ParseOptions {
+            max_value_bytes: 8,
+            ..ParseOptions::default()
+        };
+        let err = IfdReader::new(&data, endian, options)
+            .read_ifd(0)
+            .err()
+            .unwrap();
+        let too_large = err
+            .get_ref()
+            .and_then(|x| x.downcast_ref::<ValueTooLarge>())
+            .unwrap();
+        assert_eq!((too_large.tag, too_large.bytes), (TAG_A, 16));
+
+        // Permissive mode skips the entry, and still reads the rest
+        let options = ParseOptions {
+            max_value_bytes: 8,
+            ..permissive()
+        };
+        let ifd = IfdReader::new(&data, endian, options).read_ifd(0).unwrap();
+        assert!(ifd.find(TAG_A).is_none());
+        assert_eq!(ifd.skipped.len(), 1);
+        assert_eq!(ifd.skipped[0].tag, Some(TAG_A));
+        assert!(matches!(ifd.find(TAG_B), Some(Value::Short(x)) if x == &[3]));
+
+        let ifd = IfdReader::new(&data, endian, ParseOptions::default())
+            .read_ifd(0)
+            .unwrap();
+        assert!(matches!(ifd.find(TAG_A), Some(Value::Undefined(x, _)) if x.len() == 16));
+    }
+}
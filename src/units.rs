@@ -0,0 +1,208 @@
+use serde::de::Error as DeError;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt;
+
+// Units of the metadata values. Parsers convert to these as they read a value, so that the rest of
+// the program never sees a camera's native encoding. Both serialize and print as the bare number
+
+// Temperature in degrees Celsius
+#[derive(Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(in crate) struct Celsius(pub f32);
+
+// Duration in seconds
+#[derive(Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(in crate) struct Seconds(pub f32);
+
+impl fmt::Debug for Celsius {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for Celsius {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Debug for Seconds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for Seconds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+// Rounds to tenths, which is the precision cameras display exposure times and temperatures at
+pub(in crate) fn round_tenths(value: f32) -> f64 {
+    (f64::from(value) * 10.0).round() / 10.0
+}
+
+impl Celsius {
+    // Lower bound of the bin of the given width, in C, which contains this temperature
+    pub fn bin(self, width: f32) -> Celsius {
+        Celsius((self.0 / width).floor() * width)
+    }
+}
+
+// Unit which temperatures are displayed and entered in. They're always stored as Celsius
+#[derive(Clone, Copy, PartialEq)]
+pub(in crate) enum TemperatureUnit {
+    Celsius,
+    Fahrenheit,
+    Kelvin,
+}
+
+impl TemperatureUnit {
+    pub fn parse(value: &str) -> Option<TemperatureUnit> {
+        match value {
+            "c" => Some(TemperatureUnit::Celsius),
+            "f" => Some(TemperatureUnit::Fahrenheit),
+            "k" => Some(TemperatureUnit::Kelvin),
+            _ => None,
+        }
+    }
+
+    // Symbol without the degree sign, for plain text output
+    pub fn symbol(self) -> &'static str {
+        match self {
+            TemperatureUnit::Celsius => "C",
+            TemperatureUnit::Fahrenheit => "F",
+            TemperatureUnit::Kelvin => "K",
+        }
+    }
+
+    // Value of the temperature in this unit
+    pub fn convert(self, temperature: Celsius) -> f32 {
+        match self {
+            TemperatureUnit::Celsius => temperature.0,
+            TemperatureUnit::Fahrenheit => temperature.0 * 1.8 + 32.0,
+            TemperatureUnit::Kelvin => temperature.0 + 273.15,
+        }
+    }
+
+    pub fn to_celsius(self, value: f32) -> Celsius {
+        match self {
+            TemperatureUnit::Celsius => Celsius(value),
+            TemperatureUnit::Fahrenheit => Celsius((value - 32.0) / 1.8),
+            TemperatureUnit::Kelvin => Celsius(value - 273.15),
+        }
+    }
+}
+
+// With the degree sign, except for Kelvin
+impl fmt::Display for TemperatureUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemperatureUnit::Kelvin => write!(f, "K"),
+            _ => write!(f, "\u{b0}{}", self.symbol()),
+        }
+    }
+}
+
+impl Seconds {
+    // Denominator of the 1/N shutter speed closest to this exposure time
+    pub fn reciprocal(self) -> u32 {
+        (1.0 / self.0).round() as u32
+    }
+
+    // As cameras display it: 1/N up to 1/4, and whole or tenths of seconds above that
+    pub fn shutter_speed(self) -> String {
+        if self.0 > 0.0 && self.0 < 0.3 {
+            format!("1/{}", self.reciprocal())
+        } else {
+            round_tenths(self.0).to_string()
+        }
+    }
+}
+
+// Exact value of a rational field, such as an exposure time of 1/8000 s, which a float can't
+// represent. Serializes and prints as "numerator/denominator"
+#[derive(Clone, Copy)]
+pub(in crate) struct Fraction {
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+impl Fraction {
+    pub fn new(numerator: u32, denominator: u32) -> Option<Fraction> {
+        if denominator == 0 {
+            None
+        } else {
+            Some(Fraction {
+                numerator,
+                denominator,
+            })
+        }
+    }
+
+    // Parses "N/D". Plain integers are N/1
+    pub fn parse(value: &str) -> Option<Fraction> {
+        let mut parts = value.splitn(2, '/');
+        let numerator = parts.next()?.parse().ok()?;
+        let denominator = match parts.next() {
+            Some(x) => x.parse().ok()?,
+            None => 1,
+        };
+        Fraction::new(numerator, denominator)
+    }
+
+    pub fn to_f64(self) -> f64 {
+        f64::from(self.numerator) / f64::from(self.denominator)
+    }
+}
+
+// Compared by value, so 1/2 equals 2/4
+impl Ord for Fraction {
+    fn cmp(&self, other: &Fraction) -> Ordering {
+        (u64::from(self.numerator) * u64::from(other.denominator))
+            .cmp(&(u64::from(other.numerator) * u64::from(self.denominator)))
+    }
+}
+
+impl PartialOrd for Fraction {
+    fn partial_cmp(&self, other: &Fraction) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Fraction {
+    fn eq(&self, other: &Fraction) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Fraction {}
+
+impl fmt::Debug for Fraction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.numerator, self.denominator)
+    }
+}
+
+impl fmt::Display for Fraction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.numerator, self.denominator)
+    }
+}
+
+impl Serialize for Fraction {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Fraction {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Fraction, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        Fraction::parse(&value)
+            .ok_or_else(|| D::Error::custom(format!("invalid fraction: {}", value)))
+    }
+}
@@ -0,0 +1,53 @@
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+// Raw formats which are TIFF files, except that the magic number 42 has been replaced
+const ORF_MAGIC: u16 = 0x4f52;
+const ORF_SPECIAL_MAGIC: u16 = 0x5352;
+const RW2_MAGIC: u16 = 0x55;
+const VENDOR_MAGIC: &[u16] = &[ORF_MAGIC, ORF_SPECIAL_MAGIC, RW2_MAGIC];
+
+const TIFF_MAGIC: u16 = 42;
+
+fn vendor_magic(header: &[u8; 4]) -> Option<u16> {
+    let magic = match &header[..2] {
+        b"II" => LittleEndian::read_u16(&header[2..]),
+        b"MM" => BigEndian::read_u16(&header[2..]),
+        _ => return None,
+    };
+    if VENDOR_MAGIC.contains(&magic) {
+        Some(magic)
+    } else {
+        None
+    }
+}
+
+// Checks for a TIFF header with a vendor specific magic number, and rewinds the reader to the start
+// of the file
+pub(in crate) fn is_vendor_tiff<R: Read + Seek>(reader: &mut R) -> io::Result<bool> {
+    let mut header = [0u8; 4];
+    let result = reader.read_exact(&mut header);
+    reader.seek(SeekFrom::Start(0))?;
+    match result {
+        Ok(()) => Ok(vendor_magic(&header).is_some()),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+// Reads the whole file and restores the standard magic number, so that it can be parsed as a
+// regular TIFF
+pub(in crate) fn read_as_tiff<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    if data.len() < 4 {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    if data.starts_with(b"II") {
+        LittleEndian::write_u16(&mut data[2..4], TIFF_MAGIC);
+    } else {
+        BigEndian::write_u16(&mut data[2..4], TIFF_MAGIC);
+    }
+    Ok(data)
+}
@@ -0,0 +1,96 @@
+use crate::error::Error;
+use crate::metadata::MetadataParser;
+use crate::pretty;
+use crate::units::{round_tenths, TemperatureUnit};
+use log::info;
+use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
+use std::path::Path;
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+// Events are only delivered once a file has stopped changing for this long, so that frames are
+// not read while the camera is still writing them
+const DEBOUNCE_DELAY: Duration = Duration::from_secs(2);
+
+// Sidecars written by darkmagic itself
+const IGNORED_EXTENSIONS: &[&str] = &["hdr", "xmp"];
+
+fn is_ignored(path: &Path) -> bool {
+    match path.extension().and_then(|x| x.to_str()) {
+        Some(extension) => IGNORED_EXTENSIONS
+            .iter()
+            .any(|x| x.eq_ignore_ascii_case(extension)),
+        None => false,
+    }
+}
+
+// Prints the metadata of each new frame written to the directory. If max_drift is set, also warns
+// whenever the sensor temperature differs from the first frame's by more than that many degrees, in
+// the given unit. The metadata is printed as by the metadata command if pretty_output is set, and
+// with its Debug representation otherwise
+pub(in crate) fn watch(
+    directory: &Path,
+    max_drift: Option<f32>,
+    unit: TemperatureUnit,
+    pretty_output: bool,
+    parser: MetadataParser,
+) -> Result<(), Error> {
+    let (sender, receiver) = channel();
+    let mut watcher = watcher(sender, DEBOUNCE_DELAY)?;
+    watcher.watch(directory, RecursiveMode::NonRecursive)?;
+    info!("Watching {}", directory.display());
+
+    let mut initial_temperature = None;
+    for event in receiver {
+        let path = match event {
+            DebouncedEvent::Create(path)
+            | DebouncedEvent::Write(path)
+            | DebouncedEvent::Rename(_, path) => path,
+            DebouncedEvent::Error(err, _) => return Err(err.into()),
+            _ => continue,
+        };
+        if !path.is_file() || is_ignored(&path) {
+            continue;
+        }
+
+        let metadata = match parser.read_file(&path) {
+            Ok(metadata) => metadata,
+            // Reported like the batch commands, and watching continues with the next frame
+            Err(err) => {
+                eprintln!("{}: {}", path.display(), err);
+                continue;
+            }
+        };
+        if pretty_output {
+            println!(
+                "{}:\n{}",
+                path.display(),
+                pretty::format_metadata(&metadata, unit)
+            );
+        } else {
+            println!("{}: {:?}", path.display(), metadata);
+        }
+
+        // Drift is measured from the first frame which records a temperature
+        let temperature = match metadata.temperature {
+            Some(x) => x,
+            None => continue,
+        };
+        let initial = *initial_temperature.get_or_insert(temperature);
+        if let Some(max_drift) = max_drift {
+            let drift = unit.convert(temperature) - unit.convert(initial);
+            if drift.abs() > max_drift {
+                eprintln!(
+                    "{}: sensor temperature of {} {} has drifted {:+} {} since the first frame",
+                    path.display(),
+                    round_tenths(unit.convert(temperature)),
+                    unit,
+                    round_tenths(drift),
+                    unit
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
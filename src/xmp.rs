@@ -0,0 +1,322 @@
+use crate::error::Error;
+use crate::metadata::ImageMetadata;
+use crate::units::{Celsius, Fraction, Seconds};
+use log::debug;
+use roxmltree::Document;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const NS_RDF: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+const NS_TIFF: &str = "http://ns.adobe.com/tiff/1.0/";
+const NS_EXIF: &str = "http://ns.adobe.com/exif/1.0/";
+const NS_EXIF_EX: &str = "http://cipa.jp/exif/1.0/";
+const NS_AUX: &str = "http://ns.adobe.com/exif/1.0/aux/";
+// Namespace for fields which have no standard XMP property, like sensor temperature
+pub(in crate) const NS_DARKMAGIC: &str = "https://github.com/cberner/darkmagic/xmp/1.0/";
+
+// Date and time part of an XMP date, with # in place of each digit
+const DATETIME_PATTERN: &[u8] = b"####-##-##T##:##:##";
+
+// Fields found in an XMP sidecar. Any field which is present takes precedence over EXIF
+#[derive(Debug, Default)]
+pub(in crate) struct XmpMetadata {
+    // Written by darkmagic, rather than by the user or other software
+    pub generated: bool,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub serial_number: Option<String>,
+    pub sensitivity: Option<u32>,
+    pub sensitivity_type: Option<u16>,
+    pub exposure_time: Option<Seconds>,
+    pub exposure_time_fraction: Option<Fraction>,
+    pub temperature: Option<Celsius>,
+    pub capture_time: Option<String>,
+}
+
+// Sidecars are named either IMG_0001.xmp (Lightroom, Adobe DNG Converter) or IMG_0001.CR2.xmp
+// (darktable)
+pub(in crate) fn find_sidecar(path: &Path) -> Option<PathBuf> {
+    let mut appended = path.as_os_str().to_owned();
+    appended.push(".xmp");
+    vec![path.with_extension("xmp"), PathBuf::from(appended)]
+        .into_iter()
+        .find(|x| x.is_file())
+}
+
+// A property may be stored as an attribute of rdf:Description, as an element, or as the first
+// item of an rdf:Seq/Bag/Alt array
+fn get_property(doc: &Document, namespace: &str, name: &str) -> Option<String> {
+    for node in doc.descendants() {
+        if let Some(value) = node.attribute((namespace, name)) {
+            return Some(value.trim().to_string());
+        }
+        if node.has_tag_name((namespace, name)) {
+            let text = match node.descendants().find(|x| x.has_tag_name((NS_RDF, "li"))) {
+                Some(item) => item.text(),
+                None => node.text(),
+            };
+            return text.map(|x| x.trim().to_string());
+        }
+    }
+    None
+}
+
+fn get_first_property(doc: &Document, candidates: &[(&str, &str)]) -> Option<String> {
+    candidates
+        .iter()
+        .find_map(|(namespace, name)| get_property(doc, namespace, name))
+}
+
+fn parse_number<T: FromStr>(
+    value: Option<String>,
+    field_name: &'static str,
+) -> Result<Option<T>, Error> {
+    value
+        .map(|x| {
+            T::from_str(&x).map_err(|_| Error::InvalidSidecarValue {
+                field: field_name,
+                value: x,
+            })
+        })
+        .transpose()
+}
+
+// Exposure times are usually written as a rational, like "1/125"
+fn parse_exposure_time(value: Option<String>) -> Result<Option<Seconds>, Error> {
+    let value = match value {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let bad_value = || Error::InvalidSidecarValue {
+        field: "ExposureTime",
+        value: value.clone(),
+    };
+    let mut parts = value.splitn(2, '/');
+    let numerator = f32::from_str(parts.next().unwrap()).map_err(|_| bad_value())?;
+    let denominator = match parts.next() {
+        Some(x) => f32::from_str(x).map_err(|_| bad_value())?,
+        None => 1.0,
+    };
+    if denominator == 0.0 {
+        return Err(bad_value());
+    }
+    Ok(Some(Seconds(numerator / denominator)))
+}
+
+// XMP dates are ISO 8601 and may include fractional seconds and a timezone, which are dropped to
+// match the EXIF derived value
+fn parse_capture_time(value: Option<String>) -> Result<Option<String>, Error> {
+    let value = match value {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    // Also ensures that the prefix is ASCII, so that it can be sliced
+    let valid = value.len() >= DATETIME_PATTERN.len()
+        && DATETIME_PATTERN
+            .iter()
+            .zip(value.bytes())
+            .all(|(pattern, x)| match pattern {
+                b'#' => x.is_ascii_digit(),
+                _ => x == *pattern,
+            });
+    if !valid {
+        return Err(Error::InvalidSidecarValue {
+            field: "DateTimeOriginal",
+            value,
+        });
+    }
+    Ok(Some(value[..DATETIME_PATTERN.len()].to_string()))
+}
+
+pub(in crate) fn parse_xmp(data: &str) -> Result<XmpMetadata, Error> {
+    let doc = Document::parse(data)?;
+    let exposure_time = get_property(&doc, NS_EXIF, "ExposureTime");
+    Ok(XmpMetadata {
+        generated: get_property(&doc, NS_DARKMAGIC, "Generated").as_deref() == Some("True"),
+        make: get_property(&doc, NS_TIFF, "Make"),
+        model: get_property(&doc, NS_TIFF, "Model"),
+        serial_number: get_first_property(
+            &doc,
+            &[(NS_EXIF_EX, "BodySerialNumber"), (NS_AUX, "SerialNumber")],
+        ),
+        sensitivity: parse_number(
+            get_first_property(
+                &doc,
+                &[
+                    (NS_EXIF_EX, "PhotographicSensitivity"),
+                    (NS_EXIF, "ISOSpeedRatings"),
+                ],
+            ),
+            "PhotographicSensitivity",
+        )?,
+        sensitivity_type: parse_number(
+            get_property(&doc, NS_EXIF_EX, "SensitivityType"),
+            "SensitivityType",
+        )?,
+        exposure_time_fraction: exposure_time.as_deref().and_then(Fraction::parse),
+        exposure_time: parse_exposure_time(exposure_time)?,
+        temperature: parse_number(
+            get_property(&doc, NS_DARKMAGIC, "Temperature"),
+            "Temperature",
+        )?
+        .map(Celsius),
+        capture_time: parse_capture_time(get_property(&doc, NS_EXIF, "DateTimeOriginal"))?,
+    })
+}
+
+// Sidecars generated by darkmagic are ignored, since their values were read from the image, and
+// reading them again from the image keeps their confidence. Removing darkmagic:Generated from one
+// makes it an override, like any other sidecar
+pub(in crate) fn read_sidecar(path: &Path) -> Result<Option<XmpMetadata>, Error> {
+    match find_sidecar(path) {
+        Some(sidecar) => {
+            let xmp = parse_xmp(&std::fs::read_to_string(&sidecar)?)?;
+            if xmp.generated {
+                debug!("Ignoring generated sidecar {}", sidecar.display());
+                Ok(None)
+            } else {
+                Ok(Some(xmp))
+            }
+        }
+        None => Ok(None),
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// XMP stores exposure times as rationals. Without the exact value, assume a camera shutter speed,
+// which is 1/N for short exposures, and whole or tenths of seconds for long ones
+fn format_exposure_time(exposure_time: Seconds, fraction: Option<Fraction>) -> String {
+    if let Some(fraction) = fraction {
+        fraction.to_string()
+    } else if exposure_time.0 <= 0.0 {
+        "0/1".to_string()
+    } else if exposure_time.0 < 1.0 {
+        format!("1/{}", exposure_time.reciprocal())
+    } else {
+        format!("{}/10", (exposure_time.0 * 10.0).round() as u32)
+    }
+}
+
+pub(in crate) fn format_xmp(metadata: &ImageMetadata) -> String {
+    let mut properties = vec![
+        "darkmagic:Generated=\"True\"".to_string(),
+        format!("tiff:Make=\"{}\"", escape(&metadata.camera_make)),
+        format!("tiff:Model=\"{}\"", escape(&metadata.camera_model)),
+        format!(
+            "exifEX:BodySerialNumber=\"{}\"",
+            escape(&metadata.camera_serial_number)
+        ),
+        format!(
+            "exifEX:PhotographicSensitivity=\"{}\"",
+            metadata.sensor_sensitivity
+        ),
+        format!("exifEX:SensitivityType=\"{}\"", metadata.sensitivity_type),
+        format!(
+            "exif:ExposureTime=\"{}\"",
+            format_exposure_time(metadata.exposure_time, metadata.exposure_time_fraction)
+        ),
+    ];
+    // Every sensitivity the camera recorded, not just the one above
+    let sensitivities = &metadata.sensitivities;
+    let sensitivity_properties = [
+        (
+            "StandardOutputSensitivity",
+            sensitivities.standard_output_sensitivity,
+        ),
+        (
+            "RecommendedExposureIndex",
+            sensitivities.recommended_exposure_index,
+        ),
+        ("ISOSpeed", sensitivities.iso_speed),
+        ("ISOSpeedLatitudeyyy", sensitivities.iso_speed_latitude_yyy),
+        ("ISOSpeedLatitudezzz", sensitivities.iso_speed_latitude_zzz),
+    ];
+    for (name, value) in sensitivity_properties.iter() {
+        if let Some(value) = value {
+            properties.push(format!("exifEX:{}=\"{}\"", name, value));
+        }
+    }
+    if let Some(capture_time) = &metadata.capture_time {
+        properties.push(format!(
+            "exif:DateTimeOriginal=\"{}\"",
+            escape(capture_time)
+        ));
+    }
+    if let Some(temperature) = metadata.temperature {
+        properties.push(format!("darkmagic:Temperature=\"{}\"", temperature));
+    }
+
+    format!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>
+<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">
+ <rdf:RDF xmlns:rdf=\"{}\">
+  <rdf:Description rdf:about=\"\"
+    xmlns:tiff=\"{}\"
+    xmlns:exif=\"{}\"
+    xmlns:exifEX=\"{}\"
+    xmlns:darkmagic=\"{}\"
+    {}/>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end=\"w\"?>
+",
+        NS_RDF,
+        NS_TIFF,
+        NS_EXIF,
+        NS_EXIF_EX,
+        NS_DARKMAGIC,
+        properties.join("\n    ")
+    )
+}
+
+// Writes IMG_0001.xmp next to the input file. An existing sidecar is never overwritten, since it
+// may contain edits from other software
+pub(in crate) fn write_sidecar(path: &Path, metadata: &ImageMetadata) -> Result<(), Error> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path.with_extension("xmp"))?;
+    file.write_all(format_xmp(metadata).as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capture_time_drops_fraction_and_timezone() {
+        assert_eq!(
+            parse_capture_time(Some("2021-03-04T22:10:11.25+01:00".to_string())).unwrap(),
+            Some("2021-03-04T22:10:11".to_string())
+        );
+        assert_eq!(parse_capture_time(None).unwrap(), None);
+    }
+
+    #[test]
+    fn malformed_capture_time_is_an_error() {
+        // The last character is multibyte, so the 19 byte prefix ends in the middle of it
+        for value in [
+            "2021-03-04T22:10:1é",
+            "2021-03-04T22:10",
+            "2021-03-04 22:10:11",
+        ]
+        .iter()
+        {
+            assert!(
+                parse_capture_time(Some(value.to_string())).is_err(),
+                "{}",
+                value
+            );
+        }
+    }
+}